@@ -0,0 +1,116 @@
+//! Behavior cloning: supervised regression of an MLP policy onto recorded demonstrations.
+//!
+//! The network is fitted to the demonstrated actions with a mean squared error, its inputs
+//! standardized with the statistics of the demonstrated observations, which are saved
+//! with the weights like those of PPO policies.
+
+use std::io;
+
+use crate::dataset::Demonstrations;
+use crate::mlp::{Activation, Mlp};
+use crate::normalize::RunningStats;
+use crate::optim::Adam;
+use crate::rng::Rng;
+
+#[derive(Clone, Debug)]
+pub struct BcConfig {
+    pub hidden: usize,
+    pub epochs: usize,
+    pub minibatch: usize,
+    pub learning_rate: f32,
+    /// Normalized observations are clipped to `[-observation_clip, observation_clip]`
+    pub observation_clip: f32,
+    pub seed: u64,
+}
+
+impl Default for BcConfig {
+    fn default() -> Self {
+        Self {
+            hidden: 64,
+            epochs: 50,
+            minibatch: 64,
+            learning_rate: 1e-3,
+            observation_clip: 10.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Mean squared error of `mlp` over every demonstrated pair
+pub fn loss(mlp: &Mlp, demonstrations: &Demonstrations) -> f32 {
+    let total: f32 = demonstrations
+        .observations
+        .iter()
+        .zip(&demonstrations.actions)
+        .map(|(observation, action)| {
+            let output = mlp.forward(observation);
+            let error: f32 = output
+                .iter()
+                .zip(action)
+                .map(|(y, a)| (y - a).powi(2))
+                .sum();
+            error / action.len().max(1) as f32
+        })
+        .sum();
+    total / demonstrations.observations.len().max(1) as f32
+}
+
+/// Fits a fresh policy to `demonstrations`, calling `report` with the loss after every epoch
+pub fn train(
+    config: &BcConfig,
+    demonstrations: &Demonstrations,
+    mut report: impl FnMut(usize, f32),
+) -> io::Result<Mlp> {
+    let (Some(observation), Some(action)) = (
+        demonstrations.observations.first(),
+        demonstrations.actions.first(),
+    ) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no demonstrated steps to clone",
+        ));
+    };
+    let mut rng = Rng::new(config.seed);
+    let sizes = [
+        observation.len(),
+        config.hidden,
+        config.hidden,
+        action.len(),
+    ];
+    let mut mlp = Mlp::random(&sizes, Activation::Tanh, &mut rng);
+    let mut stats = RunningStats::new(observation.len());
+    for observation in &demonstrations.observations {
+        stats.update(observation);
+    }
+    mlp.input = Some(stats.normalization(config.observation_clip));
+
+    let size = mlp.parameters().len();
+    let mut optimizer = Adam::new(size, config.learning_rate);
+    let mut indices: Vec<usize> = (0..demonstrations.observations.len()).collect();
+    for epoch in 0..config.epochs {
+        // Fisher-Yates shuffle
+        for i in (1..indices.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            indices.swap(i, j);
+        }
+        for minibatch in indices.chunks(config.minibatch.max(1)) {
+            let mut grads = vec![0.0; size];
+            let scale = 2.0 / (minibatch.len() * action.len()) as f32;
+            for &i in minibatch {
+                let trace = mlp.forward_trace(&demonstrations.observations[i]);
+                let output = trace.last().expect("trace ends with the output");
+                let grad_output: Vec<f32> = output
+                    .iter()
+                    .zip(&demonstrations.actions[i])
+                    .map(|(y, a)| scale * (y - a))
+                    .collect();
+                mlp.backward(&trace, &grad_output, &mut grads);
+            }
+            let mut parameters = mlp.parameters();
+            optimizer.step(&mut parameters, &grads);
+            mlp.set_parameters(&parameters);
+        }
+        report(epoch + 1, loss(&mlp, demonstrations));
+    }
+    Ok(mlp)
+}
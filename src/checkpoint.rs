@@ -0,0 +1,75 @@
+//! Checkpoint directories letting long training runs resume after an interruption.
+//!
+//! A checkpoint holds `state.json` with the trainer counters, RNG and optimizer state,
+//! next to the weight files of its networks. Weight files are named after the generation
+//! of the state they belong to, e.g. `policy-12.mlp`, so a new generation never overwrites
+//! the files the current `state.json` points at. Every file is written to a temporary name
+//! and renamed into place, `state.json` last, and the weights of older generations are
+//! removed only once it is: an interrupted save leaves the previous checkpoint loadable.
+//!
+//! Environments are not saved: a resumed run starts fresh episodes.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::mlp::Mlp;
+
+pub const STATE: &str = "state.json";
+
+/// Whether `dir` holds a complete checkpoint
+pub fn exists(dir: &Path) -> bool {
+    dir.join(STATE).is_file()
+}
+
+fn write_atomic(path: PathBuf, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    write(&tmp)?;
+    std::fs::rename(tmp, path)
+}
+
+/// File of the network `name` saved at `generation`
+fn weights_file(name: &str, generation: u64) -> String {
+    format!("{name}-{generation}.mlp")
+}
+
+pub fn save_mlp(dir: &Path, name: &str, generation: u64, mlp: &Mlp) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    write_atomic(dir.join(weights_file(name, generation)), |tmp| {
+        mlp.save(tmp)
+    })
+}
+
+pub fn load_mlp(dir: &Path, name: &str, generation: u64) -> io::Result<Mlp> {
+    Mlp::load(dir.join(weights_file(name, generation)))
+}
+
+/// Removes the weight files of generations other than `generation`, to be called once
+/// `state.json` points at it
+pub fn prune_weights(dir: &Path, generation: u64) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let saved = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_suffix(".mlp")?.rsplit_once('-'))
+            .and_then(|(_, saved)| saved.parse::<u64>().ok());
+        if saved.is_some_and(|saved| saved != generation) {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `state.json`, to be called once every other file of the checkpoint is saved
+pub fn save_state(dir: &Path, state: &impl Serialize) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    write_atomic(dir.join(STATE), |tmp| {
+        std::fs::write(tmp, serde_json::to_vec_pretty(state)?)
+    })
+}
+
+pub fn load_state<T: DeserializeOwned>(dir: &Path) -> io::Result<T> {
+    Ok(serde_json::from_slice(&std::fs::read(dir.join(STATE))?)?)
+}
@@ -0,0 +1,237 @@
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::env::{GolemEnv, Step};
+use crate::mlp::Mlp;
+use crate::planner::{CemConfig, CemPlanner};
+
+/// Chooses the joint actions of a golem from its observation, once per step
+pub trait Controller {
+    /// Name recorded alongside the trajectories it produces
+    fn name(&self) -> String;
+    fn act(&mut self, observation: &[f32]) -> Vec<f32>;
+    /// Chooses the next action of the golem simulated by `env`, for controllers that look
+    /// ahead in it, `env` being left at the same frame
+    fn act_in(&mut self, _env: &mut GolemEnv, observation: &[f32]) -> Vec<f32> {
+        self.act(observation)
+    }
+    /// Called before the first step of every episode
+    fn reset(&mut self) {}
+}
+
+/// Steps after which a rollout is cut short, for environments without a time limit
+pub const MAX_ROLLOUT_STEPS: usize = 100_000;
+
+/// Plays one episode of `controller` seeded with `seed`, handing `visit` the observation
+/// and action of every step along with its outcome, and returns the last step
+///
+/// Episodes still running after [`MAX_ROLLOUT_STEPS`] end truncated, without an end reason.
+pub fn rollout<E>(
+    env: &mut GolemEnv,
+    controller: &mut dyn Controller,
+    seed: u64,
+    mut visit: impl FnMut(&[f32], &[f32], &Step) -> Result<(), E>,
+) -> Result<Step, E> {
+    env.reseed(seed);
+    let mut observation = env.reset();
+    controller.reset();
+    for steps in 1.. {
+        let action = controller.act_in(env, &observation);
+        let mut step = env.step(&action);
+        step.truncated |= steps >= MAX_ROLLOUT_STEPS;
+        visit(&observation, &action, &step)?;
+        if step.done() {
+            return Ok(step);
+        }
+        observation = step.observation;
+    }
+    unreachable!("rollouts end after at most {MAX_ROLLOUT_STEPS} steps")
+}
+
+/// Open loop gait alternating both limbs between extended and retracted
+pub struct ScriptedGait {
+    /// Steps spent in each half of the cycle
+    pub half_period: usize,
+    pub joints: usize,
+    step: usize,
+}
+
+impl ScriptedGait {
+    pub fn new(joints: usize) -> Self {
+        Self {
+            half_period: 30,
+            joints,
+            step: 0,
+        }
+    }
+}
+
+impl Controller for ScriptedGait {
+    fn name(&self) -> String {
+        format!("scripted-gait-{}", self.half_period)
+    }
+
+    fn act(&mut self, _observation: &[f32]) -> Vec<f32> {
+        let value = if (self.step / self.half_period).is_multiple_of(2) {
+            1.0
+        } else {
+            0.0
+        };
+        self.step += 1;
+        (0..self.joints)
+            .map(|j| {
+                if j.is_multiple_of(2) {
+                    value
+                } else {
+                    1.0 - value
+                }
+            })
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+}
+
+/// Controller whose behavior is set by a flat parameter vector, tunable by black-box optimizers
+pub trait Parametric: Controller {
+    fn parameters(&self) -> Vec<f32>;
+    fn set_parameters(&mut self, parameters: &[f32]);
+}
+
+/// Central pattern generator driving each joint with its own sine wave
+///
+/// Joint `j` is commanded `offset[j] + amplitude[j] * sin(2π frequency t + phase[j])`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CpgController {
+    /// Cycles per second
+    pub frequency: f32,
+    pub amplitude: Vec<f32>,
+    pub offset: Vec<f32>,
+    pub phase: Vec<f32>,
+    /// Seconds per step of the environment driven
+    pub dt: f32,
+    #[serde(skip)]
+    step: usize,
+}
+
+impl CpgController {
+    /// Full strokes around the middle of each joint, neighbouring joints in antiphase
+    pub fn new(joints: usize, dt: f32) -> Self {
+        Self {
+            frequency: 1.0,
+            amplitude: vec![0.5; joints],
+            offset: vec![0.5; joints],
+            phase: (0..joints)
+                .map(|j| j as f32 * std::f32::consts::PI)
+                .collect(),
+            dt,
+            step: 0,
+        }
+    }
+}
+
+impl Controller for CpgController {
+    fn name(&self) -> String {
+        format!("cpg-{:.2}hz", self.frequency)
+    }
+
+    fn act(&mut self, _observation: &[f32]) -> Vec<f32> {
+        let t = self.step as f32 * self.dt;
+        self.step += 1;
+        let angle = std::f32::consts::TAU * self.frequency * t;
+        self.offset
+            .iter()
+            .zip(&self.amplitude)
+            .zip(&self.phase)
+            .map(|((offset, amplitude), phase)| offset + amplitude * (angle + phase).sin())
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+}
+
+impl Parametric for CpgController {
+    /// Frequency, then amplitude, offset and phase of every joint
+    fn parameters(&self) -> Vec<f32> {
+        let mut parameters = vec![self.frequency];
+        parameters.extend(&self.amplitude);
+        parameters.extend(&self.offset);
+        parameters.extend(&self.phase);
+        parameters
+    }
+
+    fn set_parameters(&mut self, parameters: &[f32]) {
+        let joints = self.amplitude.len();
+        self.frequency = parameters[0];
+        self.amplitude.copy_from_slice(&parameters[1..1 + joints]);
+        self.offset
+            .copy_from_slice(&parameters[1 + joints..1 + 2 * joints]);
+        self.phase
+            .copy_from_slice(&parameters[1 + 2 * joints..1 + 3 * joints]);
+    }
+}
+
+/// Closed loop controller evaluating a network on every observation
+#[derive(Clone, Debug)]
+pub struct MlpController {
+    pub mlp: Mlp,
+    pub name: String,
+}
+
+impl Controller for MlpController {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn act(&mut self, observation: &[f32]) -> Vec<f32> {
+        self.mlp.forward(observation)
+    }
+}
+
+impl Parametric for MlpController {
+    fn parameters(&self) -> Vec<f32> {
+        self.mlp.parameters()
+    }
+
+    fn set_parameters(&mut self, parameters: &[f32]) {
+        self.mlp.set_parameters(parameters);
+    }
+}
+
+/// Controller described by `spec`: `scripted`, `cem` for the sampling planner, a CPG
+/// saved as JSON (`*.json`), or the path of an MLP weight file, which must map
+/// `observations` inputs to `joints` outputs
+pub fn load(spec: &str, observations: usize, joints: usize) -> io::Result<Box<dyn Controller>> {
+    match spec {
+        "scripted" => return Ok(Box::new(ScriptedGait::new(joints))),
+        "cem" => return Ok(Box::new(CemPlanner::new(CemConfig::default(), joints))),
+        _ => {}
+    }
+    let path = Path::new(spec);
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let cpg: CpgController = serde_json::from_slice(&std::fs::read(path)?)?;
+        return Ok(Box::new(cpg));
+    }
+    let name = path.file_stem().map_or_else(
+        || spec.to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    );
+    let mlp = Mlp::load(path)?;
+    if (mlp.input_size(), mlp.output_size()) != (observations, joints) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{spec} maps {} inputs to {} outputs, the environment has {observations} observations and {joints} actions",
+                mlp.input_size(),
+                mlp.output_size()
+            ),
+        ));
+    }
+    Ok(Box::new(MlpController { mlp, name }))
+}
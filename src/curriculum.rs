@@ -0,0 +1,144 @@
+//! Curriculum raising the difficulty of the training task with its rolling success rate.
+//!
+//! The difficulty is a level in `[0, 1]` interpolating the task from its easiest variant to
+//! the configured one, see [`EnvConfig::with_difficulty`]. An episode succeeds when it
+//! completes its task, reaching goals, climbing or simply lasting until the time limit, see
+//! [`crate::game::env::SuccessCriterion`]. Once a full window of episodes succeeds
+//! often enough the level goes up a step, it goes down a step when they fail too often, and
+//! the window starts over after every change.
+//!
+//! The level and the current window are saved with the trainer checkpoints.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::env::EnvConfig;
+
+#[derive(Clone, Debug)]
+pub struct CurriculumConfig {
+    /// Episodes the success rate is measured over
+    pub window: usize,
+    /// Success rate at or above which the level goes up
+    pub promote: f32,
+    /// Success rate at or below which the level goes down
+    pub demote: f32,
+    pub step: f32,
+    pub initial_level: f32,
+}
+
+impl Default for CurriculumConfig {
+    fn default() -> Self {
+        Self {
+            window: 20,
+            promote: 0.8,
+            demote: 0.2,
+            step: 0.1,
+            initial_level: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Curriculum {
+    #[serde(skip)]
+    pub config: CurriculumConfig,
+    pub level: f32,
+    /// Outcomes of the latest episodes played at `level`, oldest first
+    outcomes: VecDeque<bool>,
+}
+
+impl Curriculum {
+    pub fn new(config: CurriculumConfig) -> Self {
+        Self {
+            level: config.initial_level.clamp(0.0, 1.0),
+            outcomes: VecDeque::new(),
+            config,
+        }
+    }
+
+    /// Restores the progress of a saved curriculum under `config`, starting over without one
+    pub fn resume(config: CurriculumConfig, saved: Option<Curriculum>) -> Self {
+        match saved {
+            Some(saved) => Self { config, ..saved },
+            None => Self::new(config),
+        }
+    }
+
+    /// Fraction of the episodes of the current window that succeeded
+    pub fn success_rate(&self) -> Option<f32> {
+        if self.outcomes.is_empty() {
+            return None;
+        }
+        let successes = self.outcomes.iter().filter(|&&success| success).count();
+        Some(successes as f32 / self.outcomes.len() as f32)
+    }
+
+    /// Records the outcome of an episode played at the current level, returning whether
+    /// the level changed
+    pub fn record(&mut self, success: bool) -> bool {
+        self.outcomes.push_back(success);
+        while self.outcomes.len() > self.config.window.max(1) {
+            self.outcomes.pop_front();
+        }
+        let Some(rate) = self
+            .success_rate()
+            .filter(|_| self.outcomes.len() >= self.config.window)
+        else {
+            return false;
+        };
+        let level = if rate >= self.config.promote {
+            (self.level + self.config.step).min(1.0)
+        } else if rate <= self.config.demote {
+            (self.level - self.config.step).max(0.0)
+        } else {
+            self.level
+        };
+        if level == self.level {
+            return false;
+        }
+        self.level = level;
+        self.outcomes.clear();
+        true
+    }
+
+    /// Settings of `env` at the current level
+    pub fn apply(&self, env: &EnvConfig) -> EnvConfig {
+        env.with_difficulty(self.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_moves_the_level_once_per_full_window() {
+        let mut curriculum = Curriculum::new(CurriculumConfig {
+            window: 4,
+            promote: 0.75,
+            demote: 0.25,
+            step: 0.5,
+            initial_level: 0.0,
+        });
+        for _ in 0..3 {
+            assert!(!curriculum.record(true));
+        }
+        assert!(curriculum.record(true));
+        assert_eq!(curriculum.level, 0.5);
+        assert_eq!(curriculum.success_rate(), None);
+
+        // a mixed window keeps the level, the window then slides
+        for success in [true, false, true, false] {
+            assert!(!curriculum.record(success));
+        }
+        assert!(curriculum.record(false));
+        assert_eq!(curriculum.level, 0.0);
+
+        // the easiest level is as low as it goes
+        for _ in 0..4 {
+            assert!(!curriculum.record(false));
+        }
+        assert_eq!(curriculum.level, 0.0);
+    }
+}
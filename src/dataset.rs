@@ -0,0 +1,415 @@
+//! Recording of golem trajectories for offline analysis and imitation learning.
+//!
+//! Two formats are supported:
+//!
+//! - JSONL: one record per line, an `episode_start` record carrying the episode metadata,
+//!   one `step` record per transition and an `episode_end` record.
+//! - Columnar: a directory holding little-endian `f32` arrays `observations.f32`
+//!   (`steps x observation_size`), `actions.f32` (`steps x action_size`), `rewards.f32`,
+//!   a `u8` array `dones.u8` (bit 0 terminated, bit 1 truncated), and `meta.json`
+//!   listing every episode with its first step index, length and metadata.
+//!
+//! [`Demonstrations::read`] loads the observation/action pairs of either format back, along
+//! with the episode they belong to.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller::{Controller, rollout};
+use crate::game::env::GolemEnv;
+
+/// Where an episode comes from
+#[derive(Serialize, Clone, Debug)]
+pub struct EpisodeMeta {
+    pub seed: u64,
+    /// Hex encoded [`crate::game::blueprint::GolemBlueprint::hash`]
+    pub blueprint_hash: String,
+    pub controller: String,
+}
+
+/// One recorded step, `observation` being seen before `action` was taken
+#[derive(Serialize, Clone, Debug)]
+pub struct Transition<'a> {
+    pub observation: &'a [f32],
+    pub action: &'a [f32],
+    pub reward: f32,
+    pub terminated: bool,
+    pub truncated: bool,
+}
+
+pub trait TrajectoryWriter {
+    fn begin_episode(&mut self, meta: &EpisodeMeta) -> io::Result<()>;
+    fn record(&mut self, transition: &Transition) -> io::Result<()>;
+    fn end_episode(&mut self) -> io::Result<()>;
+    /// Flushes everything to disk, the dataset is incomplete until this is called
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonlRecord<'a> {
+    EpisodeStart {
+        episode: usize,
+        #[serde(flatten)]
+        meta: &'a EpisodeMeta,
+    },
+    Step {
+        episode: usize,
+        t: usize,
+        #[serde(flatten)]
+        transition: &'a Transition<'a>,
+    },
+    EpisodeEnd {
+        episode: usize,
+        length: usize,
+        #[serde(rename = "return")]
+        episode_return: f32,
+    },
+}
+
+pub struct JsonlWriter {
+    out: BufWriter<File>,
+    episode: usize,
+    t: usize,
+    episode_return: f32,
+}
+
+impl JsonlWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            episode: 0,
+            t: 0,
+            episode_return: 0.0,
+        })
+    }
+
+    fn write(&mut self, record: &JsonlRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")
+    }
+}
+
+impl TrajectoryWriter for JsonlWriter {
+    fn begin_episode(&mut self, meta: &EpisodeMeta) -> io::Result<()> {
+        self.t = 0;
+        self.episode_return = 0.0;
+        self.write(&JsonlRecord::EpisodeStart {
+            episode: self.episode,
+            meta,
+        })
+    }
+
+    fn record(&mut self, transition: &Transition) -> io::Result<()> {
+        self.write(&JsonlRecord::Step {
+            episode: self.episode,
+            t: self.t,
+            transition,
+        })?;
+        self.t += 1;
+        self.episode_return += transition.reward;
+        Ok(())
+    }
+
+    fn end_episode(&mut self) -> io::Result<()> {
+        self.write(&JsonlRecord::EpisodeEnd {
+            episode: self.episode,
+            length: self.t,
+            episode_return: self.episode_return,
+        })?;
+        self.episode += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[derive(Serialize)]
+struct ColumnarEpisode {
+    start: usize,
+    length: usize,
+    #[serde(rename = "return")]
+    episode_return: f32,
+    #[serde(flatten)]
+    meta: EpisodeMeta,
+}
+
+#[derive(Serialize, Default)]
+struct ColumnarMeta {
+    observation_size: usize,
+    action_size: usize,
+    steps: usize,
+    episodes: Vec<ColumnarEpisode>,
+}
+
+pub struct ColumnarWriter {
+    dir: PathBuf,
+    observations: BufWriter<File>,
+    actions: BufWriter<File>,
+    rewards: BufWriter<File>,
+    dones: BufWriter<File>,
+    meta: ColumnarMeta,
+}
+
+impl ColumnarWriter {
+    pub fn create(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let open = |name: &str| File::create(dir.join(name)).map(BufWriter::new);
+        Ok(Self {
+            observations: open("observations.f32")?,
+            actions: open("actions.f32")?,
+            rewards: open("rewards.f32")?,
+            dones: open("dones.u8")?,
+            meta: ColumnarMeta::default(),
+            dir,
+        })
+    }
+}
+
+fn write_f32s(out: &mut impl Write, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+impl TrajectoryWriter for ColumnarWriter {
+    fn begin_episode(&mut self, meta: &EpisodeMeta) -> io::Result<()> {
+        self.meta.episodes.push(ColumnarEpisode {
+            start: self.meta.steps,
+            length: 0,
+            episode_return: 0.0,
+            meta: meta.clone(),
+        });
+        Ok(())
+    }
+
+    fn record(&mut self, transition: &Transition) -> io::Result<()> {
+        if self.meta.steps == 0 {
+            self.meta.observation_size = transition.observation.len();
+            self.meta.action_size = transition.action.len();
+        }
+        if transition.observation.len() != self.meta.observation_size
+            || transition.action.len() != self.meta.action_size
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "transition size differs from the rest of the dataset",
+            ));
+        }
+        write_f32s(&mut self.observations, transition.observation)?;
+        write_f32s(&mut self.actions, transition.action)?;
+        write_f32s(&mut self.rewards, &[transition.reward])?;
+        let done = transition.terminated as u8 | (transition.truncated as u8) << 1;
+        self.dones.write_all(&[done])?;
+
+        self.meta.steps += 1;
+        if let Some(episode) = self.meta.episodes.last_mut() {
+            episode.length += 1;
+            episode.episode_return += transition.reward;
+        }
+        Ok(())
+    }
+
+    fn end_episode(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.observations.flush()?;
+        self.actions.flush()?;
+        self.rewards.flush()?;
+        self.dones.flush()?;
+        let meta = File::create(self.dir.join("meta.json"))?;
+        serde_json::to_writer_pretty(meta, &self.meta)?;
+        Ok(())
+    }
+}
+
+/// Runs `episodes` episodes of `controller`, episode `i` being seeded with `seed + i`
+pub fn record_rollouts(
+    env: &mut GolemEnv,
+    controller: &mut dyn Controller,
+    writer: &mut dyn TrajectoryWriter,
+    episodes: usize,
+    seed: u64,
+) -> io::Result<()> {
+    for i in 0..episodes {
+        let meta = EpisodeMeta {
+            seed: seed.wrapping_add(i as u64),
+            blueprint_hash: format!("{:016x}", env.blueprint().hash()),
+            controller: controller.name(),
+        };
+        writer.begin_episode(&meta)?;
+        rollout(env, controller, meta.seed, |observation, action, step| {
+            writer.record(&Transition {
+                observation,
+                action,
+                reward: step.reward,
+                terminated: step.terminated,
+                truncated: step.truncated,
+            })
+        })?;
+        writer.end_episode()?;
+    }
+    writer.finish()
+}
+
+/// Observation/action pairs of a recorded dataset
+#[derive(Clone, Debug, Default)]
+pub struct Demonstrations {
+    pub observations: Vec<Vec<f32>>,
+    pub actions: Vec<Vec<f32>>,
+    /// Indices of the pairs of every episode, in recording order
+    pub episodes: Vec<Range<usize>>,
+}
+
+/// Fields of a JSONL record needed to rebuild the pairs, the other ones are skipped
+#[derive(Deserialize)]
+struct JsonlPair {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    observation: Vec<f32>,
+    #[serde(default)]
+    action: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct ColumnarSpan {
+    start: usize,
+    length: usize,
+}
+
+#[derive(Deserialize)]
+struct ColumnarSizes {
+    observation_size: usize,
+    action_size: usize,
+    steps: usize,
+    episodes: Vec<ColumnarSpan>,
+}
+
+fn read_f32s(path: PathBuf, rows: usize, size: usize) -> io::Result<Vec<Vec<f32>>> {
+    let bytes = std::fs::read(&path)?;
+    if bytes.len() != rows * size * 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} does not hold {rows} rows of {size} values",
+                path.display()
+            ),
+        ));
+    }
+    let values: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Ok(values.chunks(size.max(1)).map(<[f32]>::to_vec).collect())
+}
+
+impl Demonstrations {
+    /// Reads a columnar directory or a JSONL file
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let sizes: ColumnarSizes =
+                serde_json::from_slice(&std::fs::read(path.join("meta.json"))?)?;
+            return Ok(Self {
+                observations: read_f32s(
+                    path.join("observations.f32"),
+                    sizes.steps,
+                    sizes.observation_size,
+                )?,
+                actions: read_f32s(path.join("actions.f32"), sizes.steps, sizes.action_size)?,
+                episodes: sizes
+                    .episodes
+                    .iter()
+                    .map(|span| span.start..span.start + span.length)
+                    .collect(),
+            });
+        }
+        let mut demonstrations = Self::default();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let record: JsonlPair = serde_json::from_str(&line?)?;
+            match record.kind.as_str() {
+                "episode_start" => {
+                    let start = demonstrations.observations.len();
+                    demonstrations.episodes.push(start..start);
+                }
+                "step" => {
+                    demonstrations.observations.push(record.observation);
+                    demonstrations.actions.push(record.action);
+                    if let Some(episode) = demonstrations.episodes.last_mut() {
+                        episode.end = demonstrations.observations.len();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(demonstrations)
+    }
+
+    /// Observations of episode `i`
+    pub fn episode_observations(&self, i: usize) -> Option<&[Vec<f32>]> {
+        self.episodes
+            .get(i)
+            .and_then(|episode| self.observations.get(episode.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes episodes of 2 and 3 steps, each observation holding its step index
+    fn write_episodes(writer: &mut dyn TrajectoryWriter) -> io::Result<()> {
+        let mut index = 0.0;
+        for (episode, length) in [2, 3].into_iter().enumerate() {
+            writer.begin_episode(&EpisodeMeta {
+                seed: episode as u64,
+                blueprint_hash: String::new(),
+                controller: "test".into(),
+            })?;
+            for t in 0..length {
+                writer.record(&Transition {
+                    observation: &[index],
+                    action: &[0.0, 1.0],
+                    reward: 1.0,
+                    terminated: false,
+                    truncated: t + 1 == length,
+                })?;
+                index += 1.0;
+            }
+            writer.end_episode()?;
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn reads_back_episodes_of_both_formats() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("rsrl-dataset-{}", std::process::id()));
+        let jsonl = dir.join("trajectories.jsonl");
+        let columnar = dir.join("columnar");
+        std::fs::create_dir_all(&dir)?;
+        write_episodes(&mut JsonlWriter::create(&jsonl)?)?;
+        write_episodes(&mut ColumnarWriter::create(&columnar)?)?;
+        for path in [jsonl, columnar] {
+            let demonstrations = Demonstrations::read(&path)?;
+            assert_eq!(demonstrations.episodes, [0..2, 2..5]);
+            assert_eq!(demonstrations.actions.len(), 5);
+            assert_eq!(
+                demonstrations.episode_observations(1),
+                Some(&[vec![2.0], vec![3.0], vec![4.0]][..])
+            );
+            assert_eq!(demonstrations.episode_observations(2), None);
+        }
+        std::fs::remove_dir_all(dir)
+    }
+}
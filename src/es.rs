@@ -0,0 +1,267 @@
+//! Evolution strategies (OpenAI-style) tuning the parameters of a golem controller.
+//!
+//! Every generation samples `population` gaussian perturbations of the current parameters,
+//! evaluates each one mirrored (`θ + σε` and `θ - σε`) on the same episode seeds, and moves
+//! the parameters along the rank-shaped return differences with Adam.
+//!
+//! With a [`Curriculum`], the episodes of every generation count towards its success rate
+//! and the next generation is evaluated at the resulting difficulty.
+
+use std::convert::Infallible;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint;
+use crate::controller::{Controller, Parametric, rollout};
+use crate::curriculum::{Curriculum, CurriculumConfig};
+use crate::game::env::{EnvConfig, GolemEnv};
+use crate::optim::Adam;
+use crate::rng::Rng;
+
+#[derive(Clone, Debug)]
+pub struct EsConfig {
+    /// Perturbation pairs per generation
+    pub population: usize,
+    /// Standard deviation of the perturbations
+    pub sigma: f32,
+    pub learning_rate: f32,
+    /// Episodes averaged per evaluation
+    pub episodes: usize,
+    /// Threads the rollouts are spread over, each owning a headless environment
+    pub workers: usize,
+    pub env: EnvConfig,
+    /// Difficulty schedule of `env`, trained as configured without one
+    pub curriculum: Option<CurriculumConfig>,
+}
+
+impl Default for EsConfig {
+    fn default() -> Self {
+        Self {
+            population: 16,
+            sigma: 0.05,
+            learning_rate: 0.02,
+            episodes: 1,
+            workers: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            env: EnvConfig::default(),
+            curriculum: None,
+        }
+    }
+}
+
+/// Returns observed during one generation
+#[derive(Clone, Debug)]
+pub struct Generation {
+    pub index: u64,
+    pub mean_return: f32,
+    pub best_return: f32,
+    /// Environment steps simulated during the generation
+    pub steps: u64,
+    /// Curriculum level the next generation is evaluated at
+    pub level: Option<f32>,
+}
+
+/// Checkpoint `state.json` of an ES run, the policy being saved as its flat parameters
+#[derive(Serialize, Deserialize)]
+struct EsState {
+    generation: u64,
+    rng: Rng,
+    optimizer: Adam,
+    parameters: Vec<f32>,
+    #[serde(default)]
+    curriculum: Option<Curriculum>,
+}
+
+pub struct EsTrainer<P> {
+    pub config: EsConfig,
+    pub policy: P,
+    pub optimizer: Adam,
+    pub rng: Rng,
+    pub generation: u64,
+    pub curriculum: Option<Curriculum>,
+}
+
+/// Return and length of a single episode of `controller` seeded with `seed`, and whether
+/// it completed its task
+pub fn episode_return(
+    env: &mut GolemEnv,
+    controller: &mut dyn Controller,
+    seed: u64,
+) -> (f32, usize, bool) {
+    let mut total = 0.0;
+    let mut length = 0;
+    let Ok(last) = rollout::<Infallible>(env, controller, seed, |_, _, step| {
+        total += step.reward;
+        length += 1;
+        Ok(())
+    });
+    (total, length, last.success)
+}
+
+/// Centered ranks in `[-0.5, 0.5]`, making updates insensitive to the scale of returns
+fn centered_ranks(values: &[f32]) -> Vec<f32> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let scale = (values.len().max(2) - 1) as f32;
+    for (rank, &i) in order.iter().enumerate() {
+        ranks[i] = rank as f32 / scale - 0.5;
+    }
+    ranks
+}
+
+impl<P: Parametric + Clone + Send> EsTrainer<P> {
+    pub fn new(config: EsConfig, policy: P) -> Self {
+        let size = policy.parameters().len();
+        Self {
+            optimizer: Adam::new(size, config.learning_rate),
+            rng: Rng::new(config.env.seed),
+            generation: 0,
+            curriculum: config.curriculum.clone().map(Curriculum::new),
+            config,
+            policy,
+        }
+    }
+
+    /// Restores a run saved by [`Self::save_checkpoint`], `policy` giving the shape
+    /// of the saved parameters
+    pub fn resume(config: EsConfig, mut policy: P, dir: &Path) -> io::Result<Self> {
+        let state: EsState = checkpoint::load_state(dir)?;
+        if state.parameters.len() != policy.parameters().len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checkpoint parameters do not match the policy",
+            ));
+        }
+        policy.set_parameters(&state.parameters);
+        Ok(Self {
+            curriculum: config
+                .curriculum
+                .clone()
+                .map(|config| Curriculum::resume(config, state.curriculum)),
+            config,
+            policy,
+            optimizer: state.optimizer,
+            rng: state.rng,
+            generation: state.generation,
+        })
+    }
+
+    pub fn save_checkpoint(&self, dir: &Path) -> io::Result<()> {
+        checkpoint::save_state(
+            dir,
+            &EsState {
+                generation: self.generation,
+                rng: self.rng.clone(),
+                optimizer: self.optimizer.clone(),
+                parameters: self.policy.parameters(),
+                curriculum: self.curriculum.clone(),
+            },
+        )
+    }
+
+    /// Mean return of every parameter vector over the generation's episode seeds, the
+    /// steps simulated for each and how many of its episodes completed their task
+    fn evaluate(&self, candidates: &[Vec<f32>], seed: u64) -> Vec<(f32, usize, usize)> {
+        let env = match &self.curriculum {
+            Some(curriculum) => curriculum.apply(&self.config.env),
+            None => self.config.env.clone(),
+        };
+        let workers = self.config.workers.clamp(1, candidates.len().max(1));
+        let chunk = candidates.len().div_ceil(workers).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = candidates
+                .chunks(chunk)
+                .map(|chunk| {
+                    let mut policy = self.policy.clone();
+                    let (config, env) = (&self.config, &env);
+                    scope.spawn(move || {
+                        // bevy apps are not `Send`, so each worker builds its own
+                        let mut env = GolemEnv::new(env.clone());
+                        chunk
+                            .iter()
+                            .map(|parameters| {
+                                policy.set_parameters(parameters);
+                                let (total, steps, successes) = (0..config.episodes as u64)
+                                    .map(|e| episode_return(&mut env, &mut policy, seed + e))
+                                    .fold((0.0, 0, 0), |(r, n, s), (er, en, es)| {
+                                        (r + er, n + en, s + es as usize)
+                                    });
+                                (total / config.episodes.max(1) as f32, steps, successes)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("rollout worker panicked"))
+                .collect()
+        })
+    }
+
+    /// Runs one generation and updates [`Self::policy`]
+    pub fn step(&mut self) -> Generation {
+        let center = self.policy.parameters();
+        let sigma = self.config.sigma;
+        let noise: Vec<Vec<f32>> = (0..self.config.population)
+            .map(|_| center.iter().map(|_| self.rng.normal()).collect())
+            .collect();
+        let candidates: Vec<Vec<f32>> = noise
+            .iter()
+            .flat_map(|eps| {
+                [1.0, -1.0].map(|sign| {
+                    center
+                        .iter()
+                        .zip(eps)
+                        .map(|(c, e)| c + sign * sigma * e)
+                        .collect()
+                })
+            })
+            .collect();
+
+        let seed = self
+            .config
+            .env
+            .seed
+            .wrapping_add(self.generation * self.config.episodes as u64);
+        let results = self.evaluate(&candidates, seed);
+        let returns: Vec<f32> = results.iter().map(|(r, _, _)| *r).collect();
+        let steps: usize = results.iter().map(|(_, n, _)| n).sum();
+        if let Some(curriculum) = &mut self.curriculum {
+            let episodes = self.config.episodes;
+            let outcomes = results
+                .iter()
+                .flat_map(|&(_, _, successes)| (0..episodes).map(move |e| e < successes));
+            for success in outcomes {
+                // the rest of the generation was played at the previous level
+                if curriculum.record(success) {
+                    break;
+                }
+            }
+        }
+        let ranks = centered_ranks(&returns);
+
+        let mut grad = vec![0.0; center.len()];
+        for (pair, eps) in ranks.chunks_exact(2).zip(&noise) {
+            let weight = pair[0] - pair[1];
+            for (g, e) in grad.iter_mut().zip(eps) {
+                // negated, the optimizer minimizes
+                *g -= weight * e / (noise.len() as f32 * sigma);
+            }
+        }
+        let mut parameters = center;
+        self.optimizer.step(&mut parameters, &grad);
+        self.policy.set_parameters(&parameters);
+        self.generation += 1;
+
+        Generation {
+            index: self.generation,
+            mean_return: returns.iter().sum::<f32>() / returns.len().max(1) as f32,
+            best_return: returns.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            steps: steps as u64,
+            level: self.curriculum.as_ref().map(|curriculum| curriculum.level),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Seeded headless evaluation of a controller, reported as JSON so controllers and
+//! blueprint revisions are compared on the same episodes.
+
+use serde::Serialize;
+
+use std::convert::Infallible;
+
+use crate::controller::{Controller, rollout};
+use crate::game::env::{EndReason, GolemEnv};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EpisodeStats {
+    pub seed: u64,
+    #[serde(rename = "return")]
+    pub episode_return: f32,
+    pub length: usize,
+    /// Horizontal distance covered by the head
+    pub distance: f32,
+    /// Work spent by the joint motors over the episode
+    pub energy: f32,
+    pub end: EndReason,
+    /// The episode completed its task
+    pub success: bool,
+}
+
+impl EpisodeStats {
+    /// The golem fell over or tipped past the tilt limit
+    fn fell(&self) -> bool {
+        self.end.is_terminal()
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EvalReport {
+    pub controller: String,
+    /// Hex encoded [`crate::game::blueprint::GolemBlueprint::hash`]
+    pub blueprint_hash: String,
+    pub episodes: usize,
+    pub mean_return: f32,
+    pub std_return: f32,
+    /// Share of episodes completing their task
+    pub success_rate: f32,
+    pub mean_distance: f32,
+    /// Episodes ending with the golem fallen or tipped over
+    pub falls: usize,
+    pub mean_energy: f32,
+    pub mean_length: f32,
+    pub per_episode: Vec<EpisodeStats>,
+}
+
+/// Runs `episodes` episodes of `controller`, episode `i` being seeded with `seed + i`
+pub fn evaluate(
+    env: &mut GolemEnv,
+    controller: &mut dyn Controller,
+    episodes: usize,
+    seed: u64,
+) -> EvalReport {
+    let per_episode: Vec<EpisodeStats> = (0..episodes as u64)
+        .map(|i| {
+            let seed = seed.wrapping_add(i);
+            let mut stats = EpisodeStats {
+                seed,
+                episode_return: 0.0,
+                length: 0,
+                distance: 0.0,
+                energy: 0.0,
+                end: EndReason::Interrupted,
+                success: false,
+            };
+            let Ok(last) = rollout::<Infallible>(env, controller, seed, |_, _, step| {
+                stats.episode_return += step.reward;
+                stats.energy += step.energy;
+                stats.length += 1;
+                Ok(())
+            });
+            stats.distance = last.distance;
+            stats.end = last.end_reason.unwrap_or(EndReason::Interrupted);
+            stats.success = last.success;
+            stats
+        })
+        .collect();
+
+    let n = per_episode.len().max(1) as f32;
+    let mean = |value: fn(&EpisodeStats) -> f32| per_episode.iter().map(value).sum::<f32>() / n;
+    let mean_return = mean(|e| e.episode_return);
+    let variance = mean(|e| e.episode_return * e.episode_return) - mean_return * mean_return;
+    EvalReport {
+        controller: controller.name(),
+        blueprint_hash: format!("{:016x}", env.blueprint().hash()),
+        episodes: per_episode.len(),
+        mean_return,
+        std_return: variance.max(0.0).sqrt(),
+        success_rate: mean(|e| e.success as u8 as f32),
+        mean_distance: mean(|e| e.distance),
+        falls: per_episode.iter().filter(|e| e.fell()).count(),
+        mean_energy: mean(|e| e.energy),
+        mean_length: mean(|e| e.length as f32),
+        per_episode,
+    }
+}
@@ -0,0 +1,137 @@
+/// How agent actions map onto the joints of a golem
+///
+/// Every scheme drives the per-joint `alpha` of `GolemImpluseMovement`: 0 holds a joint in
+/// its rest pose and 1 moves it fully. Discrete values are 0 (retract), 1 (hold) and 2 (extend).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ActionSpace {
+    /// One target in `[0, 1]` per joint
+    #[default]
+    Continuous,
+    /// One discrete value per joint, moving its target by `speed` per second
+    Discrete { speed: f32 },
+    /// A single discrete value moving every joint at once, as the Q/E keys do
+    Alternating { speed: f32 },
+}
+
+impl ActionSpace {
+    /// Number of values in an action for a golem with `joints` joints
+    pub fn size(&self, joints: usize) -> usize {
+        match self {
+            ActionSpace::Continuous | ActionSpace::Discrete { .. } => joints,
+            ActionSpace::Alternating { .. } => 1,
+        }
+    }
+
+    /// Highest value of an action entry, the lowest always being 0
+    pub fn high(&self) -> f32 {
+        match self {
+            ActionSpace::Continuous => 1.0,
+            ActionSpace::Discrete { .. } | ActionSpace::Alternating { .. } => 2.0,
+        }
+    }
+
+    pub fn is_discrete(&self) -> bool {
+        !matches!(self, ActionSpace::Continuous)
+    }
+
+    /// Advances the joint targets `alphas` by `dt` seconds of `action`
+    pub fn apply(&self, action: &[f32], alphas: &mut [f32], dt: f32) {
+        let step = |alpha: &mut f32, value: f32, speed: f32| {
+            let direction = value.round().clamp(0.0, 2.0) - 1.0;
+            *alpha = f32::clamp(*alpha + direction * speed * dt, 0.0, 1.0);
+        };
+        match *self {
+            ActionSpace::Continuous => {
+                for (alpha, &value) in alphas.iter_mut().zip(action) {
+                    *alpha = value.clamp(0.0, 1.0);
+                }
+            }
+            ActionSpace::Discrete { speed } => {
+                for (alpha, &value) in alphas.iter_mut().zip(action) {
+                    step(alpha, value, speed);
+                }
+            }
+            ActionSpace::Alternating { speed } => {
+                let value = action.first().copied().unwrap_or(1.0);
+                for alpha in alphas.iter_mut() {
+                    step(alpha, value, speed);
+                }
+            }
+        }
+    }
+}
+
+/// Wrappers applied on top of an [`ActionSpace`] by the environments
+#[derive(Clone, Debug)]
+pub struct ActionConfig {
+    pub space: ActionSpace,
+    /// Physics steps each action is held for
+    pub repeat: usize,
+    /// Low-pass factor in `[0, 1)` blending the previous joint targets into the new ones,
+    /// 0 applies actions as is
+    pub smoothing: f32,
+}
+
+impl Default for ActionConfig {
+    fn default() -> Self {
+        Self {
+            space: ActionSpace::default(),
+            repeat: 1,
+            smoothing: 0.0,
+        }
+    }
+}
+
+/// Joint targets of a golem, tracked across steps for integration and smoothing
+#[derive(Clone, Debug, Default)]
+pub struct ActionState {
+    commanded: Vec<f32>,
+    smoothed: Vec<f32>,
+}
+
+impl ActionState {
+    pub fn new(joints: usize) -> Self {
+        Self {
+            commanded: vec![0.0; joints],
+            smoothed: vec![0.0; joints],
+        }
+    }
+
+    /// Joint targets for the next physics step of `dt` seconds under `action`
+    pub fn advance(&mut self, config: &ActionConfig, action: &[f32], dt: f32) -> &[f32] {
+        config.space.apply(action, &mut self.commanded, dt);
+        let smoothing = config.smoothing.clamp(0.0, 0.99);
+        for (smoothed, &commanded) in self.smoothed.iter_mut().zip(&self.commanded) {
+            *smoothed = smoothing * *smoothed + (1.0 - smoothing) * commanded;
+        }
+        &self.smoothed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_moves_joint_targets() {
+        let mut alphas = [0.5, 0.5];
+        ActionSpace::Continuous.apply(&[1.5, -0.2], &mut alphas, 0.1);
+        assert_eq!(alphas, [1.0, 0.0]);
+
+        // retract, hold and extend, saturating at the ends of the stroke
+        let discrete = ActionSpace::Discrete { speed: 4.0 };
+        let mut alphas = [0.5, 0.5, 0.5];
+        discrete.apply(&[0.0, 1.0, 2.0], &mut alphas, 0.125);
+        assert_eq!(alphas, [0.0, 0.5, 1.0]);
+        discrete.apply(&[0.2, 1.4, 7.0], &mut alphas, 0.125);
+        assert_eq!(alphas, [0.0, 0.5, 1.0]);
+
+        // one value for every joint, holding them without one
+        let alternating = ActionSpace::Alternating { speed: 1.0 };
+        let mut alphas = [0.0, 1.0];
+        alternating.apply(&[2.0], &mut alphas, 0.25);
+        assert_eq!(alphas, [0.25, 1.0]);
+        alternating.apply(&[], &mut alphas, 0.25);
+        assert_eq!(alphas, [0.25, 1.0]);
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Body plan of a golem: a head carrying motorized limbs
+///
+/// Segments are numbered head first, then each limb segment followed by its tip, which is
+/// the order of [`super::randomization::PhysicsParams::densities`].
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GolemBlueprint {
+    /// Half extents of the head cuboid
+    pub head_size: Vec3,
+    pub limbs: Vec<LimbBlueprint>,
+}
+
+/// A segment sliding on a prismatic joint of the head, carrying a rigidly attached tip
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LimbBlueprint {
+    /// Rest position of the limb segment relative to the head
+    pub offset: Vec3,
+    /// Position of the tip segment relative to the limb segment
+    pub tip: Vec3,
+    /// Slide axis of the joint in the head frame
+    pub axis: Vec3,
+    /// Travel of the joint along `axis`
+    pub limits: [f32; 2],
+    /// Motor targets of an action of 0 and of 1
+    pub stroke: [f32; 2],
+    /// Half extents of both segments
+    pub size: Vec3,
+}
+
+impl Default for GolemBlueprint {
+    /// The original two-legged golem
+    fn default() -> Self {
+        let limb = LimbBlueprint {
+            offset: Vec3::new(0.0, -1.0, 0.0),
+            tip: Vec3::new(0.0, -1.0, 0.0),
+            axis: Vec3::Z,
+            limits: [0.0, 1.0],
+            stroke: [1.0, 0.0],
+            size: Vec3::splat(0.49),
+        };
+        Self {
+            head_size: Vec3::splat(0.49),
+            limbs: vec![
+                limb.clone(),
+                LimbBlueprint {
+                    offset: Vec3::new(1.0, -1.0, 0.0),
+                    tip: Vec3::new(-2.0, 0.0, 0.0),
+                    stroke: [0.0, 1.0],
+                    ..limb
+                },
+            ],
+        }
+    }
+}
+
+impl GolemBlueprint {
+    pub fn segments(&self) -> usize {
+        1 + 2 * self.limbs.len()
+    }
+
+    /// Limb of segment `index` and whether the segment is its tip, `None` for the head
+    fn limb_of(&self, index: usize) -> Option<(&LimbBlueprint, bool)> {
+        let index = index.checked_sub(1)?;
+        self.limbs.get(index / 2).map(|limb| (limb, index % 2 == 1))
+    }
+
+    /// Rest position of segment `index` relative to the head
+    pub fn offset(&self, index: usize) -> Vec3 {
+        match self.limb_of(index) {
+            Some((limb, true)) => limb.offset + limb.tip,
+            Some((limb, false)) => limb.offset,
+            None => Vec3::ZERO,
+        }
+    }
+
+    /// Index of the segment moved by limb `limb`
+    pub fn limb_segment(limb: usize) -> usize {
+        1 + 2 * limb
+    }
+
+    /// Fingerprint of the body, telling which one a dataset or policy was made for
+    pub fn hash(&self) -> u64 {
+        // FNV-1a, stable across runs and platforms unlike `DefaultHasher`
+        let head = self.head_size.to_array();
+        let limbs = self.limbs.iter().flat_map(|limb| {
+            [limb.offset, limb.tip, limb.axis, limb.size]
+                .into_iter()
+                .flat_map(|v| v.to_array())
+                .chain(limb.limits)
+                .chain(limb.stroke)
+        });
+        head.into_iter()
+            .chain(limbs)
+            .map(f32::to_bits)
+            .fold(0xcbf2_9ce4_8422_2325, |hash, word| {
+                word.to_le_bytes().iter().fold(hash, |hash, &byte| {
+                    (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+                })
+            })
+    }
+
+    /// Loads a blueprint saved as JSON, rejecting limbs whose stroke does not move them
+    pub fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let blueprint: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if let Some(i) = blueprint
+            .limbs
+            .iter()
+            .position(|limb| limb.stroke[0] == limb.stroke[1])
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("limb {i} has equal stroke ends"),
+            ));
+        }
+        Ok(blueprint)
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
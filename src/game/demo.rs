@@ -0,0 +1,138 @@
+//! Recording of the player's golem as demonstrations for behavior cloning.
+//!
+//! Every frame becomes one transition: the observation seen at the start of the frame, the
+//! joint targets the keyboard drove the golem to during it, expressed as the continuous
+//! action of the headless environments, and the resulting reward. The game must advance its
+//! clock by one physics step per frame for the transitions to match the environments' steps.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::blueprint::GolemBlueprint;
+use super::episode::{self, EpisodeEnded};
+use super::observation::Observation;
+use super::reward::StepReward;
+use super::{Golem, GolemImpluseMovement};
+use crate::dataset::{EpisodeMeta, TrajectoryWriter, Transition};
+
+/// Writes the transitions of the player's golem to a dataset
+#[derive(Resource)]
+pub struct DemoRecorder {
+    writer: Box<dyn TrajectoryWriter + Send + Sync>,
+    /// Observation of the golem before the current frame, `None` right after a reset
+    previous: Option<Vec<f32>>,
+    recording: bool,
+    episodes: usize,
+}
+
+impl DemoRecorder {
+    pub fn new(writer: Box<dyn TrajectoryWriter + Send + Sync>) -> Self {
+        Self {
+            writer,
+            previous: None,
+            recording: false,
+            episodes: 0,
+        }
+    }
+
+    fn record(
+        &mut self,
+        blueprint: &GolemBlueprint,
+        transition: &Transition,
+    ) -> std::io::Result<()> {
+        if !self.recording {
+            self.writer.begin_episode(&EpisodeMeta {
+                seed: 0,
+                blueprint_hash: format!("{:016x}", blueprint.hash()),
+                controller: "keyboard".into(),
+            })?;
+            self.recording = true;
+        }
+        self.writer.record(transition)
+    }
+
+    /// Closes the current episode and flushes the dataset, keeping it complete should the
+    /// game be killed
+    fn end_episode(&mut self) -> std::io::Result<()> {
+        self.previous = None;
+        if !self.recording {
+            return Ok(());
+        }
+        self.recording = false;
+        self.episodes += 1;
+        self.writer.end_episode()?;
+        self.writer.finish()
+    }
+}
+
+impl Drop for DemoRecorder {
+    fn drop(&mut self) {
+        match self.end_episode() {
+            Ok(()) => info!("recorded {} demonstrated episodes", self.episodes),
+            Err(e) => error!("could not save the demonstrations: {e}"),
+        }
+    }
+}
+
+fn record_demonstration(
+    mut recorder: ResMut<DemoRecorder>,
+    blueprint: Res<GolemBlueprint>,
+    golems: Query<(Entity, &Observation, &StepReward), With<Golem>>,
+    joints: Query<(&ImpulseJoint, &GolemImpluseMovement)>,
+    mut ended: EventReader<EpisodeEnded>,
+) {
+    let Ok((golem, observation, reward)) = golems.single() else {
+        return;
+    };
+    let end = ended.read().find(|event| event.golem == golem).cloned();
+
+    let result = match recorder.previous.replace(observation.0.clone()) {
+        Some(previous) => {
+            let mut movements: Vec<&GolemImpluseMovement> = joints
+                .iter()
+                .filter(|(joint, _)| joint.parent == golem)
+                .map(|(_, movement)| movement)
+                .collect();
+            // joints are numbered in limb order, like the actions of the environments
+            movements.sort_by_key(|movement| movement.index);
+            let action: Vec<f32> = movements
+                .iter()
+                .map(|movement| movement.continuous_action())
+                .collect();
+            let terminated = end.as_ref().is_some_and(|end| end.reason.is_terminal());
+            recorder.record(
+                &blueprint,
+                &Transition {
+                    observation: &previous,
+                    action: &action,
+                    reward: reward.total,
+                    terminated,
+                    truncated: end.is_some() && !terminated,
+                },
+            )
+        }
+        None => Ok(()),
+    };
+    // the golem is about to be put back at its start, its observation is stale
+    let result = result.and_then(|()| match end {
+        Some(_) => recorder.end_episode(),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        error!("could not record the demonstration: {e}");
+    }
+}
+
+/// Records the game as demonstrations into the [`DemoRecorder`] resource, when inserted
+pub struct DemoPlugin;
+impl Plugin for DemoPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            record_demonstration
+                .run_if(resource_exists::<DemoRecorder>)
+                .after(episode::track_episode)
+                .before(episode::reset_golem),
+        );
+    }
+}
@@ -0,0 +1,719 @@
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_rapier3d::prelude::*;
+
+use super::action::{ActionConfig, ActionSpace, ActionState};
+use super::blueprint::GolemBlueprint;
+pub use super::episode::{EndReason, SuccessCriterion};
+use super::episode::{Episode, EpisodeConfig, EpisodeEnded};
+use super::goal::{GOAL_OBSERVATION_SIZE, GoalConfig, GoalSampler};
+use super::imitation::{IMITATION_OBSERVATION_SIZE, ImitationConfig};
+use super::observation::{Observation, observation_size};
+use super::randomization::{PhysicsParams, RandomizationConfig};
+use super::reward::{RewardConfig, StepReward};
+use super::sensor::TerrainSensorConfig;
+use super::snapshot::Snapshot;
+use super::terrain::Terrain;
+use super::{
+    Golem, GolemImpluseMovement, GolemJointState, GolemPlugin, MovementDirty, START_POS,
+    spawn_golem, spawn_ground,
+};
+use crate::rng::Rng;
+
+/// Goals a goal-reaching episode has to reach to succeed
+const GOALS_TO_SUCCEED: u32 = 3;
+
+/// Settings shared by every environment of a run
+#[derive(Clone, Debug)]
+pub struct EnvConfig {
+    /// Simulated seconds per step
+    pub dt: f32,
+    /// Seed of the run, every random draw of an environment derives from it
+    pub seed: u64,
+    /// Largest horizontal offset applied to the golem spawn position on reset
+    pub reset_noise: f32,
+    /// Ranges the physics parameters are drawn from on every reset
+    pub randomization: RandomizationConfig,
+    pub reward: RewardConfig,
+    pub episode: EpisodeConfig,
+    pub action: ActionConfig,
+    /// Goals drawn for the golem to walk to, appended to its observation
+    pub goal: Option<GoalConfig>,
+    /// Reference motion the golem is rewarded for tracking, its phase appended to its
+    /// observation
+    pub imitation: Option<ImitationConfig>,
+    pub terrain: Terrain,
+    /// Rays measuring the terrain around the golem, their distances appended to its
+    /// observation
+    pub sensor: Option<TerrainSensorConfig>,
+    /// Body of the golems
+    pub blueprint: GolemBlueprint,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            dt: 1.0 / 60.0,
+            seed: 0,
+            reset_noise: 0.1,
+            randomization: RandomizationConfig::default(),
+            reward: RewardConfig::default(),
+            episode: EpisodeConfig {
+                auto_reset: false,
+                ..default()
+            },
+            action: ActionConfig::default(),
+            goal: None,
+            imitation: None,
+            terrain: Terrain::Flat,
+            sensor: None,
+            blueprint: GolemBlueprint::default(),
+        }
+    }
+}
+
+impl EnvConfig {
+    pub fn observation_size(&self) -> usize {
+        let goal = self.goal.as_ref().map_or(0, |_| GOAL_OBSERVATION_SIZE);
+        let imitation = self
+            .imitation
+            .as_ref()
+            .map_or(0, |_| IMITATION_OBSERVATION_SIZE);
+        let sensor = self.sensor.as_ref().map_or(0, TerrainSensorConfig::rays);
+        observation_size(self.joints()) + goal + imitation + sensor
+    }
+
+    /// Settings of a goal-reaching task, rewarding progress towards goals drawn around
+    /// the golem instead of progress along a fixed direction
+    pub fn goal_reaching(goal: GoalConfig) -> Self {
+        let config = Self::default();
+        Self {
+            reward: RewardConfig {
+                forward_progress: 0.0,
+                ..config.reward
+            },
+            goal: Some(goal),
+            episode: EpisodeConfig {
+                success: SuccessCriterion::Goals(GOALS_TO_SUCCEED),
+                ..config.episode
+            },
+            ..config
+        }
+    }
+
+    pub fn action_size(&self) -> usize {
+        self.action.space.size(self.joints())
+    }
+
+    /// Motorized joints of the golem, one per limb
+    pub fn joints(&self) -> usize {
+        self.blueprint.limbs.len()
+    }
+
+    /// Easier variant of these settings, `level` 1 leaving them as they are and 0 giving
+    /// flat terrain, the closest goals and no physics randomization
+    ///
+    /// Observation and action sizes do not depend on the level.
+    pub fn with_difficulty(&self, level: f32) -> Self {
+        let level = level.clamp(0.0, 1.0);
+        Self {
+            terrain: self.terrain.scaled(level),
+            randomization: self.randomization.scaled(level),
+            goal: self.goal.as_ref().map(|goal| goal.scaled(level)),
+            ..self.clone()
+        }
+    }
+}
+
+/// Outcome of a single environment step
+#[derive(Clone, Debug, Default)]
+pub struct Step {
+    pub observation: Vec<f32>,
+    pub reward: f32,
+    /// The episode reached a terminal state, e.g. the golem fell
+    pub terminated: bool,
+    /// The episode was cut short by the time limit
+    pub truncated: bool,
+    /// Work spent by the joint motors during the step, `|effort * velocity| * dt` summed
+    pub energy: f32,
+    /// Why the episode ended during this step, if it did
+    pub end_reason: Option<EndReason>,
+    /// Horizontal distance covered by the head since the episode start, set when it ends
+    pub distance: f32,
+    /// The episode completed its task, see [`EpisodeConfig::success`], set when it ends
+    pub success: bool,
+}
+
+impl Step {
+    pub fn done(&self) -> bool {
+        self.terminated || self.truncated
+    }
+}
+
+/// Where the golems of the next episode are spawned, one per agent
+#[derive(Resource)]
+pub(super) struct SpawnPoses(pub Vec<Transform>);
+
+/// Index of the agent controlling a golem, set on its head
+#[derive(Component, Clone, Copy, Debug)]
+pub(super) struct Agent(pub usize);
+
+fn setup_headless(
+    mut cmd: Commands,
+    poses: Res<SpawnPoses>,
+    params: Res<PhysicsParams>,
+    terrain: Res<Terrain>,
+    blueprint: Res<GolemBlueprint>,
+) {
+    spawn_ground(&mut cmd, &params, &terrain);
+    for (i, pose) in poses.0.iter().enumerate() {
+        let head = spawn_golem(&mut cmd, &blueprint, *pose, &params, None);
+        cmd.entity(head).insert(Agent(i));
+    }
+}
+
+/// Headless app simulating one golem per pose with fixed `config.dt` steps, its goals
+/// drawn from a seed taken from `rng`
+pub(super) fn build_app(
+    config: &EnvConfig,
+    poses: Vec<Transform>,
+    params: PhysicsParams,
+    rng: &mut Rng,
+) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(TransformPlugin)
+        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugins(GolemPlugin)
+        .insert_resource(TimestepMode::Fixed {
+            dt: config.dt,
+            substeps: 1,
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            config.dt,
+        )))
+        .insert_resource(config.reward.clone())
+        .insert_resource(config.episode.clone())
+        .insert_resource(SpawnPoses(poses))
+        .insert_resource(params)
+        .insert_resource(config.terrain.clone())
+        .insert_resource(config.blueprint.clone())
+        .add_systems(Startup, setup_headless);
+    if let Some(goal) = &config.goal {
+        app.insert_resource(GoalSampler {
+            config: goal.clone(),
+            rng: Rng::new(rng.next_u64()),
+        });
+    }
+    if let Some(imitation) = &config.imitation {
+        app.insert_resource(imitation.clone());
+    }
+    if let Some(sensor) = &config.sensor {
+        app.insert_resource(sensor.clone());
+    }
+    app.finish();
+    app.cleanup();
+    app
+}
+
+/// Random horizontal offset of at most `noise` along each axis
+pub(super) fn spawn_noise(rng: &mut Rng, noise: f32) -> Vec3 {
+    Vec3::new(rng.range(-noise, noise), 0.0, rng.range(-noise, noise))
+}
+
+/// Heads of every golem, ordered by agent
+pub(super) fn agents(world: &mut World) -> Vec<Entity> {
+    let mut query = world.query_filtered::<(Entity, &Agent), With<Golem>>();
+    let mut agents: Vec<_> = query.iter(world).map(|(e, agent)| (agent.0, e)).collect();
+    agents.sort_by_key(|(agent, _)| *agent);
+    agents.into_iter().map(|(_, e)| e).collect()
+}
+
+/// Runs the first frame of a freshly built world, which registers its bodies with the
+/// physics, then starts every episode over so that the caller's first step is step 1
+pub(super) fn warm_up(app: &mut App) {
+    app.update();
+    let world = app.world_mut();
+    let mut episodes = world.query::<&mut Episode>();
+    for mut episode in episodes.iter_mut(world) {
+        episode.restart();
+    }
+}
+
+/// Points the joints of the golem headed by `golem` at `targets`, in limb order
+pub(super) fn drive_joints(world: &mut World, golem: Entity, targets: &[f32]) {
+    let mut query = world.query::<(Entity, &ImpulseJoint, &mut GolemImpluseMovement)>();
+    let mut joints: Vec<_> = query
+        .iter_mut(world)
+        .filter(|(_, joint, _)| joint.parent == golem)
+        .map(|(e, _, mov)| (e, mov))
+        .collect();
+    // joints are numbered in limb order, like the observation
+    joints.sort_by_key(|(_, mov)| mov.index);
+    let mut dirty = Vec::new();
+    for ((e, mut mov), &target) in joints.into_iter().zip(targets) {
+        mov.alpha = target;
+        mov.blend = 1.0;
+        dirty.push(MovementDirty(e));
+    }
+    world.send_event_batch(dirty);
+}
+
+/// Work spent by the joint motors of the golem headed by `golem` over a step of `dt`
+pub(super) fn joint_energy(world: &mut World, golem: Entity, dt: f32) -> f32 {
+    let mut joints = world.query::<(&ImpulseJoint, &GolemJointState)>();
+    joints
+        .iter(world)
+        .filter(|(joint, _)| joint.parent == golem)
+        .map(|(_, state)| (state.effort * state.velocity).abs() * dt)
+        .sum()
+}
+
+/// Frame of a [`GolemEnv`] episode, see [`GolemEnv::snapshot`]
+#[derive(Clone)]
+pub struct EnvSnapshot {
+    world: Snapshot,
+    action: ActionState,
+    rng: Rng,
+}
+
+/// A single golem simulated in its own headless bevy app
+///
+/// Actions follow `config.action`, each one held for `repeat` physics steps of `dt`.
+/// Identical seeds and actions replay identically.
+pub struct GolemEnv {
+    config: EnvConfig,
+    rng: Rng,
+    app: App,
+    action: ActionState,
+}
+
+impl GolemEnv {
+    pub fn new(config: EnvConfig) -> Self {
+        let poses = vec![Transform::from_translation(START_POS)];
+        let rng = Rng::new(config.seed);
+        let app = build_app(&config, poses, PhysicsParams::default(), &mut rng.clone());
+        Self {
+            rng,
+            action: ActionState::new(config.joints()),
+            config,
+            app,
+        }
+    }
+
+    pub fn observation_size(&self) -> usize {
+        self.config.observation_size()
+    }
+
+    pub fn blueprint(&self) -> &GolemBlueprint {
+        &self.config.blueprint
+    }
+
+    pub fn action_space(&self) -> ActionSpace {
+        self.config.action.space
+    }
+
+    pub fn action_size(&self) -> usize {
+        self.config.action_size()
+    }
+
+    /// Replaces the settings used from the next reset on, keeping the random draws going
+    ///
+    /// `config` must keep the observation and action sizes, e.g. come from
+    /// [`EnvConfig::with_difficulty`].
+    pub fn configure(&mut self, config: EnvConfig) {
+        self.config = config;
+    }
+
+    /// Restarts the random draws of the following resets from `seed`
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Starts a new episode from a freshly built world with freshly drawn physics,
+    /// and returns its first observation
+    pub fn reset(&mut self) -> Vec<f32> {
+        let origin = START_POS + spawn_noise(&mut self.rng, self.config.reset_noise);
+        let segments = self.config.blueprint.segments();
+        let params = PhysicsParams::sample(&self.config.randomization, segments, &mut self.rng);
+        let poses = vec![Transform::from_translation(origin)];
+        self.app = build_app(&self.config, poses, params, &mut self.rng);
+        self.action = ActionState::new(self.config.joints());
+        warm_up(&mut self.app);
+        self.collect().observation
+    }
+
+    /// Captures the current frame, to branch from or rewind to with [`Self::restore`]
+    pub fn snapshot(&mut self) -> EnvSnapshot {
+        EnvSnapshot {
+            world: Snapshot::capture(self.app.world_mut()),
+            action: self.action.clone(),
+            rng: self.rng.clone(),
+        }
+    }
+
+    /// Puts the environment back to a frame captured during the current episode, the
+    /// following steps replaying exactly what they would have from that frame
+    ///
+    /// A reset builds a new world, leaving the snapshots of earlier episodes unusable.
+    pub fn restore(&mut self, snapshot: &EnvSnapshot) {
+        snapshot.world.restore(self.app.world_mut());
+        self.action = snapshot.action.clone();
+        self.rng = snapshot.rng.clone();
+    }
+
+    /// Holds `action` for `repeat` physics steps, summing their rewards and stopping early
+    /// when the episode ends
+    pub fn step(&mut self, action: &[f32]) -> Step {
+        let mut total = Step::default();
+        for _ in 0..self.config.action.repeat.max(1) {
+            let targets = self
+                .action
+                .advance(&self.config.action, action, self.config.dt);
+            let world = self.app.world_mut();
+            for golem in agents(world) {
+                drive_joints(world, golem, targets);
+            }
+
+            self.app.update();
+            let step = self.collect();
+            total = Step {
+                reward: total.reward + step.reward,
+                energy: total.energy + step.energy,
+                ..step
+            };
+            if total.done() {
+                break;
+            }
+        }
+        total
+    }
+
+    fn collect(&mut self) -> Step {
+        let world = self.app.world_mut();
+        let mut query = world.query_filtered::<(Entity, &Observation, &StepReward), With<Golem>>();
+        let (mut step, golem) = query
+            .iter(world)
+            .next()
+            .map(|(golem, observation, reward)| {
+                let step = Step {
+                    observation: observation.0.clone(),
+                    reward: reward.total,
+                    ..default()
+                };
+                (step, Some(golem))
+            })
+            .unwrap_or_default();
+        if let Some(golem) = golem {
+            step.energy = joint_energy(world, golem, self.config.dt);
+        }
+        for event in world.resource_mut::<Events<EpisodeEnded>>().drain() {
+            step.terminated |= event.reason.is_terminal();
+            step.truncated |= !event.reason.is_terminal();
+            step.end_reason = Some(event.reason);
+            step.distance = event.distance;
+            step.success = event.success;
+        }
+        step
+    }
+}
+
+enum Command {
+    Reset,
+    Step(Vec<f32>),
+    Configure(Box<EnvConfig>),
+    Reseed(u64),
+}
+
+/// Step of a worker environment, with the last observation of the episode when it ended
+/// and the environment was reset
+type WorkerStep = (Step, Option<Vec<f32>>);
+
+struct Worker {
+    commands: Sender<Command>,
+    results: Receiver<WorkerStep>,
+    handle: JoinHandle<()>,
+}
+
+/// Several golem environments stepped in lockstep, one thread each
+///
+/// Environments whose episode ends are reset right away, so the observation
+/// returned alongside `terminated` or `truncated` starts the next episode while the last
+/// one of the ended episode goes to [`VecStep::final_observations`].
+pub struct VecEnv {
+    workers: Vec<Worker>,
+}
+
+/// Outcome of stepping every environment of a [`VecEnv`], indexed by environment
+#[derive(Clone, Debug, Default)]
+pub struct VecStep {
+    pub observations: Vec<Vec<f32>>,
+    pub rewards: Vec<f32>,
+    pub terminated: Vec<bool>,
+    pub truncated: Vec<bool>,
+    /// Last observation of the episodes that ended during the step, to bootstrap
+    /// truncated ones from
+    pub final_observations: Vec<Option<Vec<f32>>>,
+    /// Whether the episodes that ended during the step completed their task
+    pub success: Vec<bool>,
+}
+
+impl VecEnv {
+    /// Environment `i` is seeded with `config.seed + i`
+    pub fn new(count: usize, config: EnvConfig) -> Self {
+        let workers = (0..count)
+            .map(|i| {
+                let (commands, command_rx) = channel();
+                let (result_tx, results) = channel();
+                let config = EnvConfig {
+                    seed: config.seed.wrapping_add(i as u64),
+                    ..config.clone()
+                };
+                // bevy apps are not `Send`, so each one is built on its own thread
+                let handle = std::thread::spawn(move || {
+                    let mut env = GolemEnv::new(config);
+                    for command in command_rx {
+                        let step = match command {
+                            Command::Reset => {
+                                let step = Step {
+                                    observation: env.reset(),
+                                    ..default()
+                                };
+                                (step, None)
+                            }
+                            Command::Step(action) => {
+                                let mut step = env.step(&action);
+                                if step.done() {
+                                    let last =
+                                        std::mem::replace(&mut step.observation, env.reset());
+                                    (step, Some(last))
+                                } else {
+                                    (step, None)
+                                }
+                            }
+                            Command::Configure(config) => {
+                                env.configure(*config);
+                                default()
+                            }
+                            Command::Reseed(seed) => {
+                                env.reseed(seed);
+                                default()
+                            }
+                        };
+                        if result_tx.send(step).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Worker {
+                    commands,
+                    results,
+                    handle,
+                }
+            })
+            .collect();
+        Self { workers }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn reset(&mut self) -> Vec<Vec<f32>> {
+        self.broadcast(|_| Command::Reset)
+            .into_iter()
+            .map(|(step, _)| step.observation)
+            .collect()
+    }
+
+    /// Replaces the settings of every environment, each one switching at its next reset
+    /// as in [`GolemEnv::configure`]
+    pub fn configure(&mut self, config: &EnvConfig) {
+        self.broadcast(|_| Command::Configure(Box::new(config.clone())));
+    }
+
+    /// Restarts the random draws of the following resets of environment `i` from `seed + i`
+    pub fn reseed(&mut self, seed: u64) {
+        self.broadcast(|i| Command::Reseed(seed.wrapping_add(i as u64)));
+    }
+
+    /// Steps every environment with its own action, `actions.len()` must match [`Self::len`]
+    pub fn step(&mut self, actions: &[Vec<f32>]) -> VecStep {
+        assert_eq!(actions.len(), self.len(), "one action per environment");
+        let mut batch = VecStep::default();
+        for (step, last) in self.broadcast(|i| Command::Step(actions[i].clone())) {
+            batch.observations.push(step.observation);
+            batch.rewards.push(step.reward);
+            batch.terminated.push(step.terminated);
+            batch.truncated.push(step.truncated);
+            batch.final_observations.push(last);
+            batch.success.push(step.success);
+        }
+        batch
+    }
+
+    fn broadcast(&mut self, command: impl Fn(usize) -> Command) -> Vec<WorkerStep> {
+        for (i, worker) in self.workers.iter().enumerate() {
+            worker
+                .commands
+                .send(command(i))
+                .expect("environment worker stopped");
+        }
+        self.workers
+            .iter()
+            .map(|worker| worker.results.recv().expect("environment worker stopped"))
+            .collect()
+    }
+}
+
+impl Drop for VecEnv {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            // closing the channel ends the worker loop
+            drop(worker.commands);
+            let _ = worker.handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bit patterns of the transforms of every rigid body, ordered by entity, equal only
+    /// for bit-for-bit identical worlds
+    fn bits(env: &mut GolemEnv) -> Vec<([u32; 3], [u32; 4])> {
+        let world = env.app.world_mut();
+        let mut query = world.query_filtered::<(Entity, &Transform), With<RigidBody>>();
+        let mut bodies: Vec<_> = query.iter(world).collect();
+        bodies.sort_by_key(|(e, _)| *e);
+        bodies
+            .into_iter()
+            .map(|(_, t)| {
+                (
+                    t.translation.to_array().map(f32::to_bits),
+                    t.rotation.to_array().map(f32::to_bits),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_identically() {
+        let (seed, steps) = (0, 300);
+        let config = EnvConfig { seed, ..default() };
+        let mut runs = [GolemEnv::new(config.clone()), GolemEnv::new(config)];
+        let mut rng = Rng::new(seed);
+        let actions: Vec<_> = (0..steps)
+            .map(|_| [rng.next_f32(), rng.next_f32()])
+            .collect();
+        for env in runs.iter_mut() {
+            env.reset();
+        }
+
+        let rewind = steps / 2;
+        let mut snapshot = None;
+        let mut trajectory = Vec::new();
+        for (i, action) in actions.iter().enumerate() {
+            let [a, b] = &mut runs;
+            if i == rewind {
+                snapshot = Some(a.snapshot());
+            }
+            a.step(action);
+            b.step(action);
+            let (ta, tb) = (bits(a), bits(b));
+            assert!(ta == tb, "trajectories diverged at step {i}");
+            if i >= rewind {
+                trajectory.push(ta);
+            }
+        }
+
+        // the first run rewound to the middle of the episode replays the rest
+        let env = &mut runs[0];
+        env.restore(&snapshot.expect("snapshot taken mid-episode"));
+        for (i, (action, expected)) in actions[rewind..].iter().zip(&trajectory).enumerate() {
+            env.step(action);
+            assert!(
+                bits(env) == *expected,
+                "rewound trajectory diverged at step {}",
+                rewind + i
+            );
+        }
+    }
+
+    /// Steps `config` with still joints until its episode ends
+    fn last_step(config: EnvConfig) -> Step {
+        let mut env = GolemEnv::new(config);
+        env.reset();
+        let action = vec![0.0; env.action_size()];
+        loop {
+            let step = env.step(&action);
+            if step.done() {
+                return step;
+            }
+        }
+    }
+
+    #[test]
+    fn reports_task_success() {
+        let survive = EnvConfig {
+            episode: EpisodeConfig {
+                max_steps: 20,
+                ..default()
+            },
+            ..default()
+        };
+        let step = last_step(survive.clone());
+        assert_eq!(step.end_reason, Some(EndReason::TimeLimit));
+        assert!(step.success);
+
+        // every heading counts at the easiest level, each step reaching a goal
+        let turn = crate::game::registry::Registry::default()
+            .make("golem-turn-v0")
+            .unwrap();
+        assert!(last_step(turn.with_difficulty(0.0)).success);
+
+        let far = EnvConfig {
+            episode: EpisodeConfig {
+                success: SuccessCriterion::Distance(100.0),
+                ..survive.episode
+            },
+            ..survive
+        };
+        assert!(!last_step(far).success);
+    }
+
+    #[test]
+    fn heights_are_measured_from_the_ground() {
+        let lowered = EnvConfig {
+            randomization: RandomizationConfig {
+                ground_height: [-4.0; 2],
+                ..default()
+            },
+            ..default()
+        };
+        let mut envs = [GolemEnv::new(default()), GolemEnv::new(lowered)];
+        let mut heights = [0.0; 2];
+        for (env, height) in envs.iter_mut().zip(&mut heights) {
+            env.reset();
+            // mid-stroke, the pose the golem stands in
+            let action = vec![0.5; env.action_size()];
+            for _ in 0..120 {
+                let step = env.step(&action);
+                assert!(!step.done(), "{:?}", step.end_reason);
+                *height = step.observation[0];
+            }
+        }
+        // dropped from further up the lowered golem settles in a slightly different pose,
+        // far from the 2 units absolute heights would differ by
+        let [nominal, lowered] = heights;
+        assert!(
+            (nominal - lowered).abs() < 0.25,
+            "{nominal} against {lowered}"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::Serialize;
+
+use super::blueprint::GolemBlueprint;
+use super::goal::Goal;
+use super::randomization::PhysicsParams;
+use super::reward::{self, StepReward};
+use super::{Golem, GolemImpluseMovement, GolemJointState};
+
+/// When a golem episode stops
+#[derive(Resource, Clone, Debug)]
+pub struct EpisodeConfig {
+    /// Terminate once the head drops below this height above the ground collider center,
+    /// [`PhysicsParams::ground_height`]
+    pub min_head_height: f32,
+    /// Terminate once the head tilts further than this from upright, in radians
+    pub max_tilt: f32,
+    /// Truncate after this many steps, 0 never truncates
+    pub max_steps: u32,
+    /// Put the golem back at its start pose when an episode ends
+    pub auto_reset: bool,
+    /// What completes the task, reported when the episode ends
+    pub success: SuccessCriterion,
+}
+
+impl Default for EpisodeConfig {
+    fn default() -> Self {
+        Self {
+            min_head_height: 1.0,
+            max_tilt: std::f32::consts::FRAC_PI_3,
+            max_steps: 1000,
+            auto_reset: true,
+            success: SuccessCriterion::default(),
+        }
+    }
+}
+
+/// Task completion of an episode, met once at any step it stays met until the episode ends
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SuccessCriterion {
+    /// Lasting until the time limit without falling
+    #[default]
+    Survive,
+    /// Reaching this many goals
+    Goals(u32),
+    /// Carrying the head this far horizontally from its start
+    Distance(f32),
+}
+
+/// Running statistics of the current episode of a golem
+#[derive(Component, Clone, Debug)]
+pub struct Episode {
+    pub steps: u32,
+    pub episode_return: f32,
+    pub goals_reached: u32,
+    /// The [`SuccessCriterion`] was met
+    pub success: bool,
+    /// Pose the golem was spawned at
+    start: Transform,
+}
+
+impl Episode {
+    /// A fresh episode of a golem spawned at `start`
+    pub fn new(start: Transform) -> Self {
+        Self {
+            steps: 0,
+            episode_return: 0.0,
+            goals_reached: 0,
+            success: false,
+            start,
+        }
+    }
+
+    /// Starts the episode over from the current frame, keeping its start pose
+    pub fn restart(&mut self) {
+        *self = Self::new(self.start);
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// The head dropped below `min_head_height`
+    Fell,
+    /// The head tilted past `max_tilt`
+    Tilted,
+    /// `max_steps` was reached
+    TimeLimit,
+    /// Reset by the player
+    Interrupted,
+}
+
+impl EndReason {
+    /// Whether the episode reached a terminal state rather than being cut short
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, EndReason::Fell | EndReason::Tilted)
+    }
+}
+
+/// Sent once per finished episode, before the golem is reset
+#[derive(Event, Clone, Debug)]
+pub struct EpisodeEnded {
+    pub golem: Entity,
+    pub reason: EndReason,
+    pub episode_return: f32,
+    pub length: u32,
+    /// Horizontal distance between the head start and end positions
+    pub distance: f32,
+    /// The episode met its [`SuccessCriterion`]
+    pub success: bool,
+}
+
+fn end_reason(
+    config: &EpisodeConfig,
+    head: &Transform,
+    ground_height: f32,
+    steps: u32,
+) -> Option<EndReason> {
+    if head.translation.y - ground_height < config.min_head_height {
+        Some(EndReason::Fell)
+    } else if head.up().angle_between(Vec3::Y) > config.max_tilt {
+        Some(EndReason::Tilted)
+    } else if config.max_steps > 0 && steps >= config.max_steps {
+        Some(EndReason::TimeLimit)
+    } else {
+        None
+    }
+}
+
+type TrackedGolem<'a> = (
+    Entity,
+    &'a Transform,
+    &'a StepReward,
+    Option<&'a Goal>,
+    &'a mut Episode,
+);
+
+pub(super) fn track_episode(
+    config: Res<EpisodeConfig>,
+    params: Res<PhysicsParams>,
+    keyboard_input: Option<Res<ButtonInput<KeyCode>>>,
+    mut query: Query<TrackedGolem, With<Golem>>,
+    mut event: EventWriter<EpisodeEnded>,
+) {
+    let interrupted = keyboard_input.is_some_and(|input| input.just_pressed(KeyCode::Space));
+    for (e, transform, reward, goal, mut episode) in query.iter_mut() {
+        episode.steps += 1;
+        episode.episode_return += reward.total;
+        episode.goals_reached += goal.is_some_and(|goal| goal.reached) as u32;
+
+        let reason = if interrupted {
+            Some(EndReason::Interrupted)
+        } else {
+            end_reason(&config, transform, params.ground_height, episode.steps)
+        };
+        let distance = (transform.translation - episode.start.translation)
+            .xz()
+            .length();
+        episode.success |= match config.success {
+            SuccessCriterion::Survive => reason == Some(EndReason::TimeLimit),
+            SuccessCriterion::Goals(goals) => episode.goals_reached >= goals,
+            SuccessCriterion::Distance(target) => distance >= target,
+        };
+        if let Some(reason) = reason {
+            event.write(EpisodeEnded {
+                golem: e,
+                reason,
+                episode_return: episode.episode_return,
+                length: episode.steps,
+                distance,
+                success: episode.success,
+            });
+        }
+    }
+}
+
+fn log_episode(mut events: EventReader<EpisodeEnded>) {
+    for event in events.read() {
+        info!(
+            "episode {} ({:?}): return {}, length {}, distance {}, success {}",
+            if event.reason.is_terminal() {
+                "terminated"
+            } else {
+                "truncated"
+            },
+            event.reason,
+            event.episode_return,
+            event.length,
+            event.distance,
+            event.success
+        );
+    }
+}
+
+type GolemPart<'a> = (
+    Entity,
+    &'a mut GolemImpluseMovement,
+    &'a mut Transform,
+    &'a mut Velocity,
+    Option<&'a mut ImpulseJoint>,
+    Option<&'a mut GolemJointState>,
+);
+
+/// Puts the golem and its limbs back where the episode started
+pub(super) fn reset_golem(
+    config: Res<EpisodeConfig>,
+    blueprint: Res<GolemBlueprint>,
+    mut events: EventReader<EpisodeEnded>,
+    mut heads: Query<(&mut Episode, &mut StepReward), With<Golem>>,
+    mut bodies: Query<GolemPart>,
+) {
+    if !config.auto_reset {
+        events.clear();
+        return;
+    }
+    for event in events.read() {
+        let Ok((mut episode, mut reward)) = heads.get_mut(event.golem) else {
+            continue;
+        };
+        let start = episode.start;
+        *episode = Episode::new(start);
+        reward.reset();
+
+        for (e, mut mov, mut transform, mut velocity, joint, state) in bodies.iter_mut() {
+            let is_part =
+                e == event.golem || joint.as_ref().is_some_and(|j| j.parent == event.golem);
+            if !is_part {
+                continue;
+            }
+            mov.alpha = 0.0;
+            mov.blend = 0.0;
+            *transform =
+                Transform::from_translation(start.transform_point(blueprint.offset(mov.index)))
+                    .with_rotation(start.rotation);
+            *velocity = Velocity::zero();
+            if let Some(mut joint) = joint
+                && let TypedJoint::PrismaticJoint(prism) = &mut joint.data
+            {
+                prism.set_motor_position(0.0, mov.stiffness, mov.damping);
+            }
+            if let Some(mut state) = state {
+                *state = GolemJointState::default();
+            }
+        }
+    }
+}
+
+/// Ends golem episodes on falls and time limits, then resets them
+pub struct EpisodePlugin;
+impl Plugin for EpisodePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EpisodeConfig>()
+            .add_event::<EpisodeEnded>()
+            .add_systems(
+                PostUpdate,
+                (track_episode, (log_episode, reset_golem))
+                    .chain()
+                    .after(reward::compute_reward),
+            );
+    }
+}
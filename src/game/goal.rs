@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+
+use super::randomization::PhysicsParams;
+use super::{Golem, GolemSensorSet};
+use crate::rng::Rng;
+
+/// Extra observation values of a golem chasing a [`Goal`]
+///
+/// Layout: goal position relative to the head in the head frame (x, y, z), then sine and
+/// cosine of the heading error (0 and 1 when the goal has no heading).
+pub const GOAL_OBSERVATION_SIZE: usize = 5;
+
+/// How goals are drawn around a golem, both when it spawns and each time it reaches one
+#[derive(Clone, Debug)]
+pub struct GoalConfig {
+    /// Horizontal distance range of a new goal from the head
+    pub distance: [f32; 2],
+    /// Horizontal distance under which the goal position counts as reached
+    pub reach_radius: f32,
+    /// Also ask for a heading, drawn uniformly, to face once at the goal
+    pub heading: bool,
+    /// Largest heading error, in radians, under which the heading counts as reached
+    pub heading_tolerance: f32,
+}
+
+impl Default for GoalConfig {
+    fn default() -> Self {
+        Self {
+            distance: [2.0, 6.0],
+            reach_radius: 0.75,
+            heading: false,
+            heading_tolerance: 0.3,
+        }
+    }
+}
+
+impl GoalConfig {
+    /// Easier goals, `level` 1 leaving them as they are and 0 drawing them at the smallest
+    /// distance with any heading counting as reached
+    pub fn scaled(&self, level: f32) -> Self {
+        let [min, max] = self.distance;
+        Self {
+            distance: [min, f32::lerp(min, max, level)],
+            heading_tolerance: f32::lerp(std::f32::consts::PI, self.heading_tolerance, level),
+            ..self.clone()
+        }
+    }
+}
+
+/// Goal sampling of the world, the golems of a world without it get no goal
+#[derive(Resource)]
+pub struct GoalSampler {
+    pub config: GoalConfig,
+    pub rng: Rng,
+}
+
+impl GoalSampler {
+    /// A goal at a random bearing around `head`
+    fn sample(&mut self, head: Vec3) -> Goal {
+        let [min, max] = self.config.distance;
+        let bearing = self.rng.range(-std::f32::consts::PI, std::f32::consts::PI);
+        let distance = self.rng.range(min, max);
+        let heading = self
+            .config
+            .heading
+            .then(|| self.rng.range(-std::f32::consts::PI, std::f32::consts::PI));
+        Goal {
+            position: head + Quat::from_rotation_y(bearing) * Vec3::Z * distance,
+            heading,
+            reached: false,
+        }
+    }
+}
+
+/// Point a golem is asked to walk its head to, redrawn once reached
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Goal {
+    /// Only the horizontal components matter
+    pub position: Vec3,
+    /// Yaw to face once at the goal, 0 facing +Z
+    pub heading: Option<f32>,
+    /// The previous goal was reached during the last step
+    pub reached: bool,
+}
+
+impl Goal {
+    /// Horizontal distance from `point` to the goal
+    pub fn distance(&self, point: Vec3) -> f32 {
+        (self.position - point).xz().length()
+    }
+
+    /// Signed heading error of a head rotated by `rotation`, 0 without a heading
+    pub fn heading_error(&self, rotation: Quat) -> f32 {
+        self.heading.map_or(0.0, |heading| {
+            let forward = rotation * Vec3::Z;
+            let error = heading - forward.x.atan2(forward.z);
+            // wrapped to [-pi, pi]
+            error.sin().atan2(error.cos())
+        })
+    }
+
+    /// Observation values of a golem whose head is at `head`
+    pub fn observation(&self, head: &Transform) -> [f32; GOAL_OBSERVATION_SIZE] {
+        let offset = head.rotation.inverse() * (self.position - head.translation);
+        let error = self.heading_error(head.rotation);
+        [offset.x, offset.y, offset.z, error.sin(), error.cos()]
+    }
+}
+
+/// Marker showing where the goal of a golem is
+#[derive(Component)]
+struct GoalMarker(Entity);
+
+fn assign_goals(
+    mut cmd: Commands,
+    mut sampler: ResMut<GoalSampler>,
+    golems: Query<(Entity, &Transform, Has<Goal>), With<Golem>>,
+) {
+    for (golem, transform, _) in golems.iter().filter(|(_, _, has_goal)| !has_goal) {
+        cmd.entity(golem)
+            .insert(sampler.sample(transform.translation));
+    }
+}
+
+/// Flags the goals reached during the last step and draws the next ones
+fn update_goals(
+    mut sampler: ResMut<GoalSampler>,
+    mut golems: Query<(&Transform, &mut Goal), With<Golem>>,
+) {
+    for (transform, mut goal) in golems.iter_mut() {
+        let close = goal.distance(transform.translation) < sampler.config.reach_radius;
+        let facing =
+            goal.heading_error(transform.rotation).abs() < sampler.config.heading_tolerance;
+        if close && facing {
+            *goal = sampler.sample(transform.translation);
+            goal.reached = true;
+        } else {
+            goal.reached = false;
+        }
+        // kept level with the head, only the horizontal offset is meaningful
+        goal.position.y = transform.translation.y;
+    }
+}
+
+fn spawn_goal_markers(
+    mut cmd: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    golems: Query<Entity, Added<Goal>>,
+) {
+    for golem in golems.iter() {
+        cmd.spawn((
+            GoalMarker(golem),
+            Mesh3d(meshes.add(Cylinder::new(0.5, 0.1))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::linear_rgb(0.9, 0.6, 0.1),
+                unlit: true,
+                ..Default::default()
+            })),
+            Transform::default(),
+        ));
+    }
+}
+
+fn move_goal_markers(
+    params: Res<PhysicsParams>,
+    goals: Query<&Goal>,
+    mut markers: Query<(&GoalMarker, &mut Transform)>,
+    mut gizmos: Gizmos,
+) {
+    for (marker, mut transform) in markers.iter_mut() {
+        let Ok(goal) = goals.get(marker.0) else {
+            continue;
+        };
+        // resting on top of the ground slab
+        transform.translation = goal.position.with_y(params.ground_height + 0.15);
+        if let Some(heading) = goal.heading {
+            let direction = Quat::from_rotation_y(heading) * Vec3::Z;
+            let start = transform.translation + Vec3::Y * 0.1;
+            gizmos.arrow(start, start + direction, Color::linear_rgb(0.9, 0.6, 0.1));
+        }
+    }
+}
+
+/// Draws goals for the golems of worlds holding a [`GoalSampler`]
+pub struct GoalPlugin;
+impl Plugin for GoalPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostStartup,
+            assign_goals.run_if(resource_exists::<GoalSampler>),
+        )
+        .add_systems(
+            PostUpdate,
+            update_goals
+                .run_if(resource_exists::<GoalSampler>)
+                .in_set(GolemSensorSet)
+                .before(super::observation::update_observation),
+        );
+    }
+}
+
+/// Shows the goals of the game golems as markers on the ground
+pub struct GoalMarkerPlugin;
+impl Plugin for GoalMarkerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (spawn_goal_markers, move_goal_markers).chain());
+    }
+}
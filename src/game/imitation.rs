@@ -0,0 +1,211 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::episode::Episode;
+use super::observation::observation_size;
+use super::{Golem, GolemImpluseMovement, GolemJointState, GolemSensorSet};
+
+/// Extra observation values of an imitating golem: sine and cosine of its phase along the
+/// reference motion
+pub const IMITATION_OBSERVATION_SIZE: usize = 2;
+
+/// Offset of the first joint position in an [`super::observation::Observation`], each
+/// followed by the joint velocity
+const JOINT_OBSERVATION_OFFSET: usize = observation_size(0);
+
+/// Joint positions of a golem sampled every `dt` seconds, in limb order
+#[derive(Clone, Debug)]
+pub struct ReferenceMotion {
+    pub dt: f32,
+    pub frames: Vec<Vec<f32>>,
+    /// Loop back to the first frame after the last one, as a gait does
+    pub cyclic: bool,
+}
+
+/// Pose and joint velocities of a [`ReferenceMotion`] at one phase
+#[derive(Clone, Debug, Default)]
+pub struct ReferenceFrame {
+    pub positions: Vec<f32>,
+    pub velocities: Vec<f32>,
+}
+
+impl ReferenceMotion {
+    /// Motion played by a recorded sequence of observations of a golem with `joints` joints,
+    /// e.g. one episode of a dataset, `dt` apart
+    pub fn from_observations(
+        dt: f32,
+        joints: usize,
+        observations: &[Vec<f32>],
+        cyclic: bool,
+    ) -> Self {
+        let frames = observations
+            .iter()
+            .map(|observation| {
+                observation
+                    .iter()
+                    .skip(JOINT_OBSERVATION_OFFSET)
+                    .step_by(2)
+                    .take(joints)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        Self { dt, frames, cyclic }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.len() as f32 * self.dt
+    }
+
+    /// `phase` brought back into the motion, wrapped when cyclic and clamped otherwise
+    fn wrap(&self, phase: f32) -> f32 {
+        let duration = self.duration();
+        if duration <= 0.0 {
+            0.0
+        } else if self.cyclic {
+            phase.rem_euclid(duration)
+        } else {
+            phase.clamp(0.0, duration - self.dt)
+        }
+    }
+
+    fn index(&self, phase: f32) -> usize {
+        ((self.wrap(phase) / self.dt) as usize).min(self.frames.len().saturating_sub(1))
+    }
+
+    /// Reference pose at `phase` seconds, velocities taken towards the next frame
+    pub fn frame(&self, phase: f32) -> ReferenceFrame {
+        let Some(positions) = self.frames.get(self.index(phase)) else {
+            return ReferenceFrame::default();
+        };
+        let next = &self.frames[self.index(phase + self.dt)];
+        ReferenceFrame {
+            positions: positions.clone(),
+            velocities: positions
+                .iter()
+                .zip(next)
+                .map(|(position, next)| (next - position) / self.dt)
+                .collect(),
+        }
+    }
+
+    /// Squared distance between `positions` and the reference pose at `phase`
+    fn pose_error(&self, phase: f32, positions: &[f32]) -> f32 {
+        self.frames.get(self.index(phase)).map_or(0.0, |frame| {
+            frame
+                .iter()
+                .zip(positions)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum()
+        })
+    }
+}
+
+/// Motion imitated by the golems of a world, the golems of a world without it imitate nothing
+#[derive(Resource, Clone, Debug)]
+pub struct ImitationConfig {
+    pub motion: ReferenceMotion,
+    /// How far ahead of its nominal phase, in seconds, a golem may be matched to the
+    /// reference, letting it catch up after lagging behind
+    pub window: f32,
+}
+
+impl ImitationConfig {
+    pub fn new(motion: ReferenceMotion) -> Self {
+        Self {
+            motion,
+            window: 0.1,
+        }
+    }
+}
+
+/// Progress of a golem along the reference motion
+#[derive(Component, Clone, Debug, Default)]
+pub struct Imitation {
+    /// Seconds into the reference motion
+    pub phase: f32,
+    /// Reference at `phase`, tracked by the imitation reward terms
+    pub target: ReferenceFrame,
+}
+
+impl Imitation {
+    /// Observation values of the golem for a motion of `duration` seconds
+    pub fn observation(&self, duration: f32) -> [f32; IMITATION_OBSERVATION_SIZE] {
+        let angle = std::f32::consts::TAU * self.phase / duration.max(f32::EPSILON);
+        [angle.sin(), angle.cos()]
+    }
+}
+
+fn assign_imitation(
+    mut cmd: Commands,
+    config: Res<ImitationConfig>,
+    golems: Query<(Entity, Has<Imitation>), With<Golem>>,
+) {
+    for (golem, _) in golems.iter().filter(|(_, imitating)| !imitating) {
+        cmd.entity(golem).insert(Imitation {
+            phase: 0.0,
+            target: config.motion.frame(0.0),
+        });
+    }
+}
+
+/// Advances the phase of every golem by the step duration, then aligns it to the reference
+/// frame closest to the golem pose within the alignment window
+///
+/// The phase never moves backwards: a golem lagging behind the reference holds its phase
+/// while one ahead skips forward.
+fn align_imitation(
+    time: Res<Time>,
+    config: Res<ImitationConfig>,
+    joints: Query<(&ImpulseJoint, &GolemImpluseMovement, &GolemJointState)>,
+    mut golems: Query<(Entity, Option<&Episode>, &mut Imitation), With<Golem>>,
+) {
+    let motion = &config.motion;
+    for (e, episode, mut imitation) in golems.iter_mut() {
+        if episode.is_some_and(|episode| episode.steps == 0) {
+            imitation.phase = 0.0;
+        }
+        let mut limbs: Vec<_> = joints
+            .iter()
+            .filter(|(joint, _, _)| joint.parent == e)
+            .map(|(_, mov, state)| (mov.index, state.position))
+            .collect();
+        limbs.sort_by_key(|(index, _)| *index);
+        let positions: Vec<f32> = limbs.into_iter().map(|(_, position)| position).collect();
+
+        let start = imitation.phase;
+        // reference frames may hold several physics steps, e.g. recorded with an action repeat
+        let nominal = start + time.delta_secs();
+        let candidates = ((time.delta_secs() + config.window) / motion.dt).ceil() as usize;
+        // ties go to the nominal phase, checked first
+        let phase = std::iter::once(nominal)
+            .chain((0..=candidates).map(|i| start + i as f32 * motion.dt))
+            .min_by(|a, b| {
+                motion
+                    .pose_error(*a, &positions)
+                    .total_cmp(&motion.pose_error(*b, &positions))
+            })
+            .unwrap_or(start);
+        imitation.phase = motion.wrap(phase);
+        imitation.target = motion.frame(imitation.phase);
+    }
+}
+
+/// Tracks a reference motion with the golems of worlds holding an [`ImitationConfig`]
+pub struct ImitationPlugin;
+impl Plugin for ImitationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostStartup,
+            assign_imitation.run_if(resource_exists::<ImitationConfig>),
+        )
+        .add_systems(
+            PostUpdate,
+            align_imitation
+                .run_if(resource_exists::<ImitationConfig>)
+                .in_set(GolemSensorSet)
+                .after(super::update_joint_state)
+                .before(super::observation::update_observation),
+        );
+    }
+}
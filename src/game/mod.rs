@@ -3,27 +3,42 @@ use bevy_rapier3d::prelude::*;
 
 mod cuboid_uvcustom;
 use cuboid_uvcustom::CuboidTiled;
+pub mod action;
+pub mod blueprint;
 mod camera;
+pub mod demo;
+pub mod env;
+mod episode;
+pub mod goal;
+pub mod imitation;
+pub mod multi;
+mod observation;
+mod policy;
+mod randomization;
+pub mod registry;
+mod reward;
+pub mod sensor;
+mod snapshot;
+pub mod terrain;
+use action::ActionSpace;
+use blueprint::GolemBlueprint;
+pub use policy::PolicyDriver;
+use randomization::PhysicsParams;
+use terrain::Terrain;
 
 const ALPHA_SPEED: f32 = 3.0;
 const START_POS: Vec3 = Vec3::new(0.0, 3.0, 0.0);
-const GOLEM_OFFSET: [Vec3; 5] = [
-    Vec3::new(0.0, 0.0, 0.0),
-    Vec3::new(0.0, -1.0, 0.0),
-    Vec3::new(0.0, -2.0, 0.0),
-    Vec3::new(1.0, -1.0, 0.0),
-    Vec3::new(-1.0, -1.0, 0.0),
-];
-
-const IMPLUSE_ADDITION_POS_FROM: [f32; 5] = [0f32, 1f32, 1f32, 0f32, 0f32];
-const IMPLUSE_ADDITION_POS_TO: [f32; 5] = [0f32, 0f32, 0f32, 1f32, 1f32];
 // 0-1
-#[derive(Component)]
+#[derive(Component, Clone)]
 struct GolemImpluseMovement {
     alpha: f32, // 0-1 具体偏移
     blend: f32, // 0-1 预表现应用偏移
+    /// Segment index in the [`GolemBlueprint`]
     index: usize,
+    /// Motor targets of an alpha of 0 and of 1
+    stroke: [f32; 2],
     stiffness: f32,
+    damping: f32,
 }
 impl Default for GolemImpluseMovement {
     fn default() -> Self {
@@ -31,7 +46,9 @@ impl Default for GolemImpluseMovement {
             alpha: 0.0,
             blend: 0.0,
             index: 0,
+            stroke: [0.0, 0.0],
             stiffness: 1000.0,
+            damping: 0.0,
         }
     }
 }
@@ -42,160 +59,274 @@ impl GolemImpluseMovement {
             ..Default::default()
         }
     }
+
+    fn with_stroke(self, stroke: [f32; 2]) -> Self {
+        Self { stroke, ..self }
+    }
+
+    fn with_motor(self, stiffness: f32, damping: f32) -> Self {
+        Self {
+            stiffness,
+            damping,
+            ..self
+        }
+    }
+
+    /// Motor position the joint is currently driven towards
+    fn target(&self) -> f32 {
+        f32::lerp(self.stroke[0], self.stroke[1], self.alpha) * self.blend
+    }
+
+    /// Continuous action driving the joint to its current target, as a policy would with
+    /// a full blend, 0 for a joint without travel
+    fn continuous_action(&self) -> f32 {
+        let [from, to] = self.stroke;
+        if to == from {
+            return 0.0;
+        }
+        (self.target() - from) / (to - from)
+    }
+}
+
+/// Measured state of a golem joint, refreshed after every physics step
+#[derive(Component, Default, Clone, Copy, Debug)]
+struct GolemJointState {
+    /// Current offset along the joint axis
+    position: f32,
+    /// Rate of change of `position` over the last step
+    velocity: f32,
+    limits: [f32; 2],
+    /// Force the motor spends pulling the joint towards its target
+    effort: f32,
 }
 
 #[derive(Component)]
 struct Golem;
 
-fn make_golem_mesh_head() -> CuboidTiled {
+/// Meshes are slightly larger than the colliders they cover
+const MESH_MARGIN: f32 = 0.01;
+
+fn make_golem_mesh_head(half_size: Vec3) -> CuboidTiled {
     CuboidTiled {
-        half_size: Vec3::new(0.5, 0.5, 0.5),
+        half_size: half_size + MESH_MARGIN,
         tile_count: [2, 2],
         face: [[1, 0], [1, 1], [1, 1], [1, 1], [0, 1], [0, 1]],
     }
 }
-fn make_golem_mesh_body() -> CuboidTiled {
+fn make_golem_mesh_body(half_size: Vec3) -> CuboidTiled {
     CuboidTiled {
-        half_size: Vec3::new(0.5, 0.5, 0.5),
+        half_size: half_size + MESH_MARGIN,
         tile_count: [2, 2],
         face: [[0, 0]; 6],
     }
 }
 
-fn setup_scene(
-    mut cmd: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-) {
+/// Render handles of the golem segments
+struct GolemVisuals {
+    head: Handle<Mesh>,
+    /// Mesh of the segments of each limb
+    bodies: Vec<Handle<Mesh>>,
+    material: Handle<StandardMaterial>,
+}
+
+fn spawn_ground(cmd: &mut Commands, params: &PhysicsParams, terrain: &Terrain) {
     cmd.spawn((
         Collider::cuboid(100.0, 0.1, 100.0),
-        Transform::from_xyz(0.0, -2.0, 0.0),
+        Friction::new(params.friction),
+        Transform::from_xyz(0.0, params.ground_height, 0.0),
     ));
+    for (collider, transform) in terrain.colliders(params.ground_height + 0.1) {
+        cmd.spawn((collider, Friction::new(params.friction), transform));
+    }
+}
 
-    let texture_handle = asset_server.load::<Image>("golem_tex.png");
-
+/// Spawns a golem built after `blueprint` with its head at `pose` and returns the head,
+/// `visuals` is `None` when headless
+fn spawn_golem(
+    cmd: &mut Commands,
+    blueprint: &GolemBlueprint,
+    pose: Transform,
+    params: &PhysicsParams,
+    visuals: Option<&GolemVisuals>,
+) -> Entity {
+    let place = |index: usize| {
+        Transform::from_translation(pose.transform_point(blueprint.offset(index)))
+            .with_rotation(pose.rotation)
+    };
+    let cuboid = |size: Vec3| Collider::cuboid(size.x, size.y, size.z);
     let parent = cmd
         .spawn((
             Golem {},
+            reward::StepReward::default(),
+            episode::Episode::new(pose),
+            observation::Observation::default(),
             RigidBody::Dynamic,
-            Collider::cuboid(0.49, 0.49, 0.49),
-            Transform::from_translation(START_POS + GOLEM_OFFSET[0]),
-            Mesh3d(meshes.add(make_golem_mesh_head())),
-            MeshMaterial3d(materials.add(StandardMaterial {
-                base_color_texture: Some(texture_handle.clone()),
-                unlit: true,
-                ..Default::default()
-            })),
+            Velocity::zero(),
+            cuboid(blueprint.head_size),
+            params.segment(0),
+            place(0),
             GolemImpluseMovement::from_index(0),
         ))
         .id();
+    if let Some(visuals) = visuals {
+        cmd.entity(parent).insert((
+            Mesh3d(visuals.head.clone()),
+            MeshMaterial3d(visuals.material.clone()),
+        ));
+    }
 
-    cmd.spawn((
-        GolemImpluseMovement::from_index(1),
-        RigidBody::Dynamic,
-        Collider::cuboid(0.49, 0.49, 0.49),
-        Transform::from_translation(START_POS + GOLEM_OFFSET[1]),
-        ImpulseJoint::new(
-            parent,
-            PrismaticJointBuilder::new(Vec3::Z)
-                .local_anchor1(Vec3::ZERO)
-                .local_anchor2(-GOLEM_OFFSET[1])
-                .limits([0.0, 1.0])
-                .motor_position(0.0, 512.0, 0.0),
-        ),
-        Mesh3d(meshes.add(make_golem_mesh_body())),
-        MeshMaterial3d(materials.add(StandardMaterial {
-            base_color_texture: Some(texture_handle.clone()),
-            unlit: true,
-            ..Default::default()
-        })),
-        children![(
-            Collider::cuboid(0.49, 0.49, 0.49),
-            Transform::from_translation(GOLEM_OFFSET[2] - GOLEM_OFFSET[1]),
-            Mesh3d(meshes.add(make_golem_mesh_body())),
-            MeshMaterial3d(materials.add(StandardMaterial {
-                base_color_texture: Some(texture_handle.clone()),
-                unlit: true,
-                ..Default::default()
-            })),
-        )],
-    ));
-    cmd.spawn((
-        GolemImpluseMovement::from_index(3),
-        RigidBody::Dynamic,
-        Collider::cuboid(0.49, 0.49, 0.49),
-        Transform::from_translation(START_POS + GOLEM_OFFSET[3]),
-        ImpulseJoint::new(
-            parent,
-            PrismaticJointBuilder::new(Vec3::Z)
-                .local_anchor1(Vec3::ZERO)
-                .local_anchor2(-GOLEM_OFFSET[3])
-                .limits([0.0, 1.0])
-                .motor_position(0.0, 512.0, 0.0),
-        ),
-        Mesh3d(meshes.add(make_golem_mesh_body())),
-        MeshMaterial3d(materials.add(StandardMaterial {
-            base_color_texture: Some(texture_handle.clone()),
+    for (i, limb_blueprint) in blueprint.limbs.iter().enumerate() {
+        let index = GolemBlueprint::limb_segment(i);
+        let limb = cmd
+            .spawn((
+                GolemImpluseMovement::from_index(index)
+                    .with_stroke(limb_blueprint.stroke)
+                    .with_motor(params.motor_stiffness, params.motor_damping),
+                GolemJointState::default(),
+                RigidBody::Dynamic,
+                Velocity::zero(),
+                cuboid(limb_blueprint.size),
+                params.segment(index),
+                place(index),
+                ImpulseJoint::new(
+                    parent,
+                    PrismaticJointBuilder::new(limb_blueprint.axis.normalize_or(Vec3::Z))
+                        .local_anchor1(Vec3::ZERO)
+                        .local_anchor2(-limb_blueprint.offset)
+                        .limits(limb_blueprint.limits)
+                        .motor_position(0.0, params.motor_stiffness, params.motor_damping),
+                ),
+            ))
+            .id();
+        let segment = cmd
+            .spawn((
+                ChildOf(limb),
+                cuboid(limb_blueprint.size),
+                params.segment(index + 1),
+                Transform::from_translation(limb_blueprint.tip),
+            ))
+            .id();
+        if let Some(visuals) = visuals {
+            for e in [limb, segment] {
+                cmd.entity(e).insert((
+                    Mesh3d(visuals.bodies[i].clone()),
+                    MeshMaterial3d(visuals.material.clone()),
+                ));
+            }
+        }
+    }
+    parent
+}
+
+fn setup_scene(
+    mut cmd: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    params: Res<PhysicsParams>,
+    terrain: Res<Terrain>,
+    blueprint: Res<GolemBlueprint>,
+) {
+    spawn_ground(&mut cmd, &params, &terrain);
+
+    let texture_handle = asset_server.load::<Image>("golem_tex.png");
+    let visuals = GolemVisuals {
+        head: meshes.add(make_golem_mesh_head(blueprint.head_size)),
+        bodies: blueprint
+            .limbs
+            .iter()
+            .map(|limb| meshes.add(make_golem_mesh_body(limb.size)))
+            .collect(),
+        material: materials.add(StandardMaterial {
+            base_color_texture: Some(texture_handle),
             unlit: true,
             ..Default::default()
-        })),
-        children![(
-            Collider::cuboid(0.49, 0.49, 0.49),
-            Transform::from_translation(GOLEM_OFFSET[4] - GOLEM_OFFSET[3]),
-            Mesh3d(meshes.add(make_golem_mesh_body())),
-            MeshMaterial3d(materials.add(StandardMaterial {
-                base_color_texture: Some(texture_handle.clone()),
-                unlit: true,
-                ..Default::default()
-            })),
-        )],
-    ));
+        }),
+    };
+    spawn_golem(
+        &mut cmd,
+        &blueprint,
+        Transform::from_translation(START_POS),
+        &params,
+        Some(&visuals),
+    );
 }
 
-fn handle_on_reset(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+#[derive(Event)]
+struct MovementDirty(Entity);
 
-    mut query: Query<&mut Transform, With<Golem>>,
-) {
-    for mut transform in query.iter_mut() {
-        if keyboard_input.just_pressed(KeyCode::Space) {
-            transform.translation.y += 1.0;
-            transform.translation.x = 0.0;
-            transform.translation.z = 0.0;
-        }
+/// Systems refreshing golem measurements once physics has been written back
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+struct GolemSensorSet;
+
+/// Action space the keyboard drives the golem with, cycled with Tab
+#[derive(Resource)]
+struct KeyboardScheme(ActionSpace);
+
+impl Default for KeyboardScheme {
+    fn default() -> Self {
+        Self(ActionSpace::Alternating { speed: ALPHA_SPEED })
     }
 }
 
-#[derive(Event)]
-struct MovementDirty(Entity);
+/// Extend and retract keys of each limb, the alternating scheme only reads the first pair
+const LIMB_KEYS: [(KeyCode, KeyCode); 2] = [
+    (KeyCode::KeyE, KeyCode::KeyQ),
+    (KeyCode::KeyR, KeyCode::KeyF),
+];
 
 fn handle_move_body_key(
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut query: Query<(Entity, &mut GolemImpluseMovement)>,
+    mut scheme: ResMut<KeyboardScheme>,
+    mut query: Query<(Entity, &mut GolemImpluseMovement), With<ImpulseJoint>>,
     time: Res<Time>,
     mut event: EventWriter<MovementDirty>,
 ) {
-    for (e, mut mov) in query.iter_mut() {
-        let mut changed = false;
-        if keyboard_input.pressed(KeyCode::KeyE) {
-            changed = true;
-            if mov.blend == 0f32 {
-                mov.alpha = 0.0;
-            }
-            mov.alpha = f32::clamp(mov.alpha + time.delta_secs() * ALPHA_SPEED, 0.0, 1.0);
-        } else if keyboard_input.pressed(KeyCode::KeyQ) {
-            changed = true;
-            if mov.blend == 0f32 {
-                mov.alpha = 1.0;
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        scheme.0 = match scheme.0 {
+            ActionSpace::Alternating { speed } => ActionSpace::Discrete { speed },
+            ActionSpace::Discrete { .. } => ActionSpace::Continuous,
+            ActionSpace::Continuous => ActionSpace::Alternating { speed: ALPHA_SPEED },
+        };
+        info!("keyboard action space: {:?}", scheme.0);
+    }
+    let keys = &LIMB_KEYS[..scheme.0.size(LIMB_KEYS.len())];
+    if !keys
+        .iter()
+        .any(|&(extend, retract)| keyboard_input.any_pressed([extend, retract]))
+    {
+        return;
+    }
+    let action: Vec<f32> = keys
+        .iter()
+        .map(|&(extend, retract)| match scheme.0 {
+            ActionSpace::Continuous => keyboard_input.pressed(extend) as u8 as f32,
+            _ if keyboard_input.pressed(extend) => 2.0,
+            _ if keyboard_input.pressed(retract) => 0.0,
+            _ => 1.0,
+        })
+        .collect();
+
+    let mut joints: Vec<_> = query.iter_mut().collect();
+    joints.sort_by_key(|(_, mov)| mov.index);
+    let mut alphas: Vec<f32> = joints
+        .iter()
+        .map(|(_, mov)| {
+            // a resting golem starts moving from the end opposite to the key pressed
+            if mov.blend != 0.0 {
+                mov.alpha
+            } else if action[0] == 0.0 {
+                1.0
+            } else {
+                0.0
             }
-            mov.alpha = f32::clamp(mov.alpha - time.delta_secs() * ALPHA_SPEED, 0.0, 1.0);
-        }
-        // emit changed event
-        if changed {
-            mov.blend = f32::clamp(mov.blend + time.delta_secs() * ALPHA_SPEED, 0.0, 1.0);
-            event.write(MovementDirty(e));
-        }
+        })
+        .collect();
+    scheme.0.apply(&action, &mut alphas, time.delta_secs());
+    for ((e, mut mov), alpha) in joints.into_iter().zip(alphas) {
+        mov.alpha = alpha;
+        mov.blend = f32::clamp(mov.blend + time.delta_secs() * ALPHA_SPEED, 0.0, 1.0);
+        event.write(MovementDirty(e));
     }
 }
 
@@ -205,23 +336,69 @@ fn handle_movement(
 ) {
     for event in events.read() {
         let result = query.get_mut(event.0);
-        if let Ok((mov, mut joint)) = result {
-            match &mut joint.data {
-                TypedJoint::PrismaticJoint(prism) => {
-                    prism.set_motor_position(
-                        f32::lerp(
-                            IMPLUSE_ADDITION_POS_FROM[mov.index],
-                            IMPLUSE_ADDITION_POS_TO[mov.index],
-                            mov.alpha,
-                        ) * mov.blend,
-                        mov.stiffness,
-                        0.0,
-                    );
-                    println!("alpha: {}, blend: {}", mov.alpha, mov.blend);
-                }
-                _ => {}
-            }
+        if let Ok((mov, mut joint)) = result
+            && let TypedJoint::PrismaticJoint(prism) = &mut joint.data
+        {
+            prism.set_motor_position(mov.target(), mov.stiffness, mov.damping);
+            debug!("alpha: {}, blend: {}", mov.alpha, mov.blend);
+        }
+    }
+}
+
+fn update_joint_state(
+    time: Res<Time>,
+    parents: Query<&Transform>,
+    mut query: Query<(&ImpulseJoint, &Transform, &mut GolemJointState)>,
+) {
+    let dt = time.delta_secs();
+    for (joint, transform, mut state) in query.iter_mut() {
+        let TypedJoint::PrismaticJoint(prism) = &joint.data else {
+            continue;
+        };
+        let Ok(parent) = parents.get(joint.parent) else {
+            continue;
+        };
+        // anchor of the child expressed in the parent frame, projected on the slide axis
+        let anchor2 = transform.transform_point(prism.local_anchor2());
+        let local =
+            parent.rotation.inverse() * (anchor2 - parent.translation) - prism.local_anchor1();
+        let position = local.dot(prism.local_axis1());
+
+        if dt > 0.0 {
+            state.velocity = (position - state.position) / dt;
         }
+        state.position = position;
+        if let Some(limits) = prism.limits() {
+            state.limits = [limits.min, limits.max];
+        }
+        state.effort = prism.motor().map_or(0.0, |motor| {
+            motor.stiffness * (motor.target_pos - position) - motor.damping * state.velocity
+        });
+    }
+}
+
+/// Golem simulation shared by the game and the headless environments, without rendering or input
+pub struct GolemPlugin;
+impl Plugin for GolemPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(reward::RewardPlugin)
+            .add_plugins(episode::EpisodePlugin)
+            .add_plugins(goal::GoalPlugin)
+            .add_plugins(imitation::ImitationPlugin)
+            .add_plugins(sensor::TerrainSensorPlugin)
+            .add_event::<MovementDirty>()
+            .init_resource::<PhysicsParams>()
+            .init_resource::<Terrain>()
+            .init_resource::<GolemBlueprint>()
+            .add_systems(Startup, randomization::apply_gravity)
+            .add_systems(PostUpdate, handle_movement.before(PhysicsSet::SyncBackend))
+            .add_systems(
+                PostUpdate,
+                (update_joint_state, observation::update_observation)
+                    .chain()
+                    .in_set(GolemSensorSet)
+                    .after(PhysicsSet::Writeback),
+            );
     }
 }
 
@@ -231,9 +408,18 @@ impl Plugin for GameModule {
         app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
             .add_plugins(RapierDebugRenderPlugin::default())
             .add_plugins(camera::PlayerPlugin)
-            .add_event::<MovementDirty>()
+            .add_plugins(GolemPlugin)
+            .add_plugins(goal::GoalMarkerPlugin)
+            .add_plugins(demo::DemoPlugin)
             .add_systems(Startup, setup_scene)
-            .add_systems(PostUpdate, handle_on_reset)
-            .add_systems(PostUpdate, (handle_move_body_key, handle_movement).chain());
+            .init_resource::<KeyboardScheme>()
+            .add_systems(PostUpdate, handle_move_body_key.before(handle_movement))
+            .add_systems(
+                PostUpdate,
+                policy::drive_with_policy
+                    .run_if(resource_exists::<PolicyDriver>)
+                    .after(handle_move_body_key)
+                    .before(handle_movement),
+            );
     }
 }
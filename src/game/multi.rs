@@ -0,0 +1,240 @@
+use bevy::prelude::*;
+
+use super::START_POS;
+use super::action::ActionState;
+use super::env::{EnvConfig, agents, build_app, drive_joints, spawn_noise, warm_up};
+use super::episode::EpisodeEnded;
+use super::observation::Observation;
+use super::randomization::PhysicsParams;
+use super::reward::StepReward;
+use crate::rng::Rng;
+
+/// What the golems sharing a scene are asked to do
+#[derive(Clone, Debug)]
+pub enum MultiTask {
+    /// Golems side by side, `spacing` apart, all rewarded with their mean reward
+    Cooperative { agents: usize, spacing: f32 },
+    /// Two golems facing each other `separation` apart, the first one pushed out of the
+    /// ring of `ring_radius`, fallen or tipped over loses
+    Sumo { ring_radius: f32, separation: f32 },
+}
+
+impl MultiTask {
+    pub fn agents(&self) -> usize {
+        match self {
+            MultiTask::Cooperative { agents, .. } => *agents,
+            MultiTask::Sumo { .. } => 2,
+        }
+    }
+
+    /// Spawn pose of every agent, before the reset noise
+    fn poses(&self) -> Vec<Transform> {
+        match *self {
+            MultiTask::Cooperative { agents, spacing } => (0..agents)
+                .map(|i| {
+                    let x = (i as f32 - (agents - 1) as f32 / 2.0) * spacing;
+                    Transform::from_translation(START_POS + Vec3::X * x)
+                })
+                .collect(),
+            MultiTask::Sumo { separation, .. } => vec![
+                Transform::from_translation(START_POS - Vec3::Z * separation / 2.0),
+                Transform::from_translation(START_POS + Vec3::Z * separation / 2.0)
+                    .with_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+            ],
+        }
+    }
+}
+
+/// Reward of a sumo bout for its winner, the loser receiving the opposite
+const SUMO_OUTCOME: f32 = 1.0;
+
+/// Outcome of a multi-agent step, indexed by agent
+///
+/// The episode is shared: it ends for every agent as soon as one of them ends.
+#[derive(Clone, Debug, Default)]
+pub struct MultiStep {
+    pub observations: Vec<Vec<f32>>,
+    pub rewards: Vec<f32>,
+    pub terminated: bool,
+    pub truncated: bool,
+    /// Agent that won a competitive episode
+    pub winner: Option<usize>,
+}
+
+impl MultiStep {
+    pub fn done(&self) -> bool {
+        self.terminated || self.truncated
+    }
+}
+
+/// Several golems sharing one headless physics world
+///
+/// Every agent observes its own golem as in [`super::env::GolemEnv`], followed by the
+/// head position of every other agent in its own head frame, and acts on its own joints.
+pub struct MultiGolemEnv {
+    config: EnvConfig,
+    task: MultiTask,
+    rng: Rng,
+    app: App,
+    actions: Vec<ActionState>,
+}
+
+impl MultiGolemEnv {
+    pub fn new(config: EnvConfig, task: MultiTask) -> Self {
+        let rng = Rng::new(config.seed);
+        let app = build_app(
+            &config,
+            task.poses(),
+            PhysicsParams::default(),
+            &mut rng.clone(),
+        );
+        Self {
+            rng,
+            actions: vec![ActionState::new(config.joints()); task.agents()],
+            config,
+            task,
+            app,
+        }
+    }
+
+    pub fn agents(&self) -> usize {
+        self.task.agents()
+    }
+
+    pub fn observation_size(&self) -> usize {
+        self.config.observation_size() + 3 * (self.agents() - 1)
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Starts a new episode in a freshly built world, returning every agent's observation
+    pub fn reset(&mut self) -> Vec<Vec<f32>> {
+        let poses = self
+            .task
+            .poses()
+            .into_iter()
+            .map(|pose| {
+                let noise = spawn_noise(&mut self.rng, self.config.reset_noise);
+                pose.with_translation(pose.translation + noise)
+            })
+            .collect();
+        let segments = self.config.blueprint.segments();
+        let params = PhysicsParams::sample(&self.config.randomization, segments, &mut self.rng);
+        self.app = build_app(&self.config, poses, params, &mut self.rng);
+        self.actions = vec![ActionState::new(self.config.joints()); self.agents()];
+        warm_up(&mut self.app);
+        self.collect().observations
+    }
+
+    /// Applies one action per agent for `repeat` physics steps
+    pub fn step(&mut self, actions: &[Vec<f32>]) -> MultiStep {
+        assert_eq!(actions.len(), self.agents(), "one action per agent");
+        let mut total = MultiStep {
+            rewards: vec![0.0; self.agents()],
+            ..default()
+        };
+        for _ in 0..self.config.action.repeat.max(1) {
+            let world = self.app.world_mut();
+            for ((golem, state), action) in agents(world)
+                .into_iter()
+                .zip(&mut self.actions)
+                .zip(actions)
+            {
+                let targets = state.advance(&self.config.action, action, self.config.dt);
+                drive_joints(world, golem, targets);
+            }
+            self.app.update();
+            let step = self.collect();
+            for (sum, reward) in total.rewards.iter_mut().zip(&step.rewards) {
+                *sum += reward;
+            }
+            total = MultiStep {
+                rewards: total.rewards,
+                ..step
+            };
+            if total.done() {
+                break;
+            }
+        }
+        total
+    }
+
+    fn collect(&mut self) -> MultiStep {
+        let world = self.app.world_mut();
+        let golems = agents(world);
+        let heads: Vec<Transform> = golems
+            .iter()
+            .map(|&golem| *world.get::<Transform>(golem).expect("golem head"))
+            .collect();
+        let mut step = MultiStep::default();
+        let mut base = Vec::new();
+        for (i, &golem) in golems.iter().enumerate() {
+            let mut observation = world
+                .get::<Observation>(golem)
+                .map_or_else(Vec::new, |observation| observation.0.clone());
+            let head = heads[i];
+            for (j, other) in heads.iter().enumerate() {
+                if j != i {
+                    let relative = head.rotation.inverse() * (other.translation - head.translation);
+                    observation.extend(relative.to_array());
+                }
+            }
+            step.observations.push(observation);
+
+            let reward = world.get::<StepReward>(golem);
+            base.push(match self.task {
+                // facing each other, progress along the shared forward axis means nothing
+                MultiTask::Sumo { .. } => reward.map_or(0.0, |reward| {
+                    let forward = reward
+                        .terms
+                        .iter()
+                        .find(|(name, _)| *name == "forward_progress");
+                    reward.total - forward.map_or(0.0, |(_, value)| *value)
+                }),
+                MultiTask::Cooperative { .. } => reward.map_or(0.0, |reward| reward.total),
+            });
+        }
+
+        let mut out = vec![false; golems.len()];
+        for event in world.resource_mut::<Events<EpisodeEnded>>().drain() {
+            let Some(i) = golems.iter().position(|&golem| golem == event.golem) else {
+                continue;
+            };
+            if event.reason.is_terminal() {
+                out[i] = true;
+            } else {
+                step.truncated = true;
+            }
+        }
+
+        match self.task {
+            MultiTask::Cooperative { .. } => {
+                let team = base.iter().sum::<f32>() / base.len().max(1) as f32;
+                step.rewards = vec![team; base.len()];
+                step.terminated = out.iter().any(|&out| out);
+            }
+            MultiTask::Sumo { ring_radius, .. } => {
+                for (out, head) in out.iter_mut().zip(&heads) {
+                    *out |= (head.translation - START_POS).xz().length() > ring_radius;
+                }
+                step.rewards = base;
+                let losers = out.iter().filter(|&&out| out).count();
+                step.terminated = losers > 0;
+                // both out at once is a draw
+                if losers == 1 {
+                    for (i, (reward, &out)) in step.rewards.iter_mut().zip(&out).enumerate() {
+                        if out {
+                            *reward -= SUMO_OUTCOME;
+                        } else {
+                            *reward += SUMO_OUTCOME;
+                            step.winner = Some(i);
+                        }
+                    }
+                }
+            }
+        }
+        step
+    }
+}
@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::goal::Goal;
+use super::imitation::{Imitation, ImitationConfig};
+use super::randomization::PhysicsParams;
+use super::sensor::TerrainSensor;
+use super::{Golem, GolemImpluseMovement, GolemJointState};
+
+/// Length of an [`Observation`] of a golem with `joints` joints and no [`Goal`]
+pub const fn observation_size(joints: usize) -> usize {
+    11 + 2 * joints
+}
+
+/// Flat observation vector of a golem, refreshed after every physics step
+///
+/// Layout: head height above [`PhysicsParams::ground_height`], head rotation (x, y, z, w), head linear velocity,
+/// head angular velocity, then position and velocity of each joint ordered by limb index,
+/// followed by the [`Goal::observation`] of golems chasing a goal, the
+/// [`Imitation::observation`] of golems imitating a reference motion and the ray distances
+/// of golems carrying a [`TerrainSensor`].
+#[derive(Component, Default, Clone, Debug)]
+pub struct Observation(pub Vec<f32>);
+
+type ObservedGolem<'a> = (
+    Entity,
+    &'a Transform,
+    &'a Velocity,
+    Option<&'a Goal>,
+    Option<&'a Imitation>,
+    Option<&'a TerrainSensor>,
+    &'a mut Observation,
+);
+
+pub(super) fn update_observation(
+    imitation_config: Option<Res<ImitationConfig>>,
+    params: Res<PhysicsParams>,
+    joints: Query<(&ImpulseJoint, &GolemImpluseMovement, &GolemJointState)>,
+    mut query: Query<ObservedGolem, With<Golem>>,
+) {
+    for (e, transform, velocity, goal, imitation, sensor, mut observation) in query.iter_mut() {
+        let mut limbs: Vec<_> = joints
+            .iter()
+            .filter(|(joint, _, _)| joint.parent == e)
+            .map(|(_, mov, state)| (mov.index, state))
+            .collect();
+        limbs.sort_by_key(|(index, _)| *index);
+
+        let obs = &mut observation.0;
+        obs.clear();
+        obs.push(transform.translation.y - params.ground_height);
+        obs.extend(transform.rotation.to_array());
+        obs.extend(velocity.linvel.to_array());
+        obs.extend(velocity.angvel.to_array());
+        for (_, state) in limbs {
+            obs.push(state.position);
+            obs.push(state.velocity);
+        }
+        if let Some(goal) = goal {
+            obs.extend(goal.observation(transform));
+        }
+        if let Some(imitation) = imitation
+            && let Some(config) = &imitation_config
+        {
+            obs.extend(imitation.observation(config.motion.duration()));
+        }
+        if let Some(sensor) = sensor {
+            obs.extend(&sensor.0);
+        }
+    }
+}
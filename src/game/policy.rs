@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::action::{ActionConfig, ActionState};
+use super::episode::EpisodeEnded;
+use super::observation::Observation;
+use super::{Golem, GolemImpluseMovement, MovementDirty};
+use crate::mlp::Mlp;
+
+/// Network driving every golem of the game from its observation the way the headless
+/// environments apply actions: through `action`, each output held for `action.repeat`
+/// frames, observations going through the network's saved normalization.
+///
+/// Frames must be physics steps of the environment's `dt` for the policy to see what it
+/// was trained on.
+#[derive(Resource)]
+pub struct PolicyDriver {
+    policy: Mlp,
+    action: ActionConfig,
+    golems: HashMap<Entity, Controlled>,
+}
+
+impl PolicyDriver {
+    pub fn new(policy: Mlp, action: ActionConfig) -> Self {
+        Self {
+            policy,
+            action,
+            golems: HashMap::new(),
+        }
+    }
+}
+
+/// Action a golem holds and the frames left before the policy picks the next one
+struct Controlled {
+    state: ActionState,
+    action: Vec<f32>,
+    held: usize,
+}
+
+pub(super) fn drive_with_policy(
+    mut driver: ResMut<PolicyDriver>,
+    time: Res<Time>,
+    golems: Query<(Entity, &Observation), With<Golem>>,
+    mut joints: Query<(Entity, &ImpulseJoint, &mut GolemImpluseMovement)>,
+    mut ended: EventReader<EpisodeEnded>,
+    mut event: EventWriter<MovementDirty>,
+) {
+    let driver = &mut *driver;
+    // reset golems start over from resting joint targets, like a reset environment
+    for end in ended.read() {
+        driver.golems.remove(&end.golem);
+    }
+    for (golem, observation) in golems.iter() {
+        if observation.0.len() != driver.policy.input_size() {
+            warn_once!(
+                "policy expects {} inputs, observations have {}",
+                driver.policy.input_size(),
+                observation.0.len()
+            );
+            continue;
+        }
+        let mut limbs: Vec<_> = joints
+            .iter_mut()
+            .filter(|(_, joint, _)| joint.parent == golem)
+            .map(|(e, _, mov)| (e, mov))
+            .collect();
+        limbs.sort_by_key(|(_, mov)| mov.index);
+
+        let controlled = driver.golems.entry(golem).or_insert_with(|| Controlled {
+            state: ActionState::new(limbs.len()),
+            action: Vec::new(),
+            held: 0,
+        });
+        if controlled.held == 0 {
+            controlled.action = driver.policy.forward(&observation.0);
+            controlled.held = driver.action.repeat.max(1);
+        }
+        controlled.held -= 1;
+        let targets =
+            controlled
+                .state
+                .advance(&driver.action, &controlled.action, time.delta_secs());
+        for ((e, mut mov), &alpha) in limbs.into_iter().zip(targets) {
+            mov.alpha = alpha;
+            mov.blend = 1.0;
+            event.write(MovementDirty(e));
+        }
+    }
+}
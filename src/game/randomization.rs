@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::rng::Rng;
+
+/// Density of the golem segments without randomization
+const NOMINAL_DENSITY: f32 = 1.0;
+
+/// Ranges the physics of each episode are drawn from, `[min, max]`
+///
+/// The default ranges are collapsed on the nominal values, i.e. no randomization.
+#[derive(Clone, Debug)]
+pub struct RandomizationConfig {
+    /// Collider density of each golem segment
+    pub density: [f32; 2],
+    /// Friction coefficient of the golem and the ground
+    pub friction: [f32; 2],
+    pub motor_stiffness: [f32; 2],
+    pub motor_damping: [f32; 2],
+    /// Magnitude of the downward gravity
+    pub gravity: [f32; 2],
+    /// Height of the ground surface collider center
+    pub ground_height: [f32; 2],
+}
+
+impl Default for RandomizationConfig {
+    fn default() -> Self {
+        let nominal = PhysicsParams::default();
+        Self {
+            density: [NOMINAL_DENSITY; 2],
+            friction: [nominal.friction; 2],
+            motor_stiffness: [nominal.motor_stiffness; 2],
+            motor_damping: [nominal.motor_damping; 2],
+            gravity: [nominal.gravity; 2],
+            ground_height: [nominal.ground_height; 2],
+        }
+    }
+}
+
+impl RandomizationConfig {
+    /// Ranges shrunk towards the nominal values, `level` 1 leaving them as they are and 0
+    /// collapsing them
+    pub fn scaled(&self, level: f32) -> Self {
+        let nominal = Self::default();
+        let scale = |range: [f32; 2], nominal: [f32; 2]| {
+            [
+                f32::lerp(nominal[0], range[0], level),
+                f32::lerp(nominal[1], range[1], level),
+            ]
+        };
+        Self {
+            density: scale(self.density, nominal.density),
+            friction: scale(self.friction, nominal.friction),
+            motor_stiffness: scale(self.motor_stiffness, nominal.motor_stiffness),
+            motor_damping: scale(self.motor_damping, nominal.motor_damping),
+            gravity: scale(self.gravity, nominal.gravity),
+            ground_height: scale(self.ground_height, nominal.ground_height),
+        }
+    }
+}
+
+/// Physics parameters of the current episode, read when spawning the scene
+#[derive(Resource, Clone, Debug)]
+pub struct PhysicsParams {
+    /// Density of each segment, indexed like the blueprint segments, the segments past the
+    /// end having the nominal density
+    pub densities: Vec<f32>,
+    pub friction: f32,
+    pub motor_stiffness: f32,
+    pub motor_damping: f32,
+    pub gravity: f32,
+    pub ground_height: f32,
+}
+
+impl Default for PhysicsParams {
+    fn default() -> Self {
+        Self {
+            densities: Vec::new(),
+            friction: 0.5,
+            motor_stiffness: 1000.0,
+            motor_damping: 0.0,
+            gravity: 9.81,
+            ground_height: -2.0,
+        }
+    }
+}
+
+impl PhysicsParams {
+    /// Parameters of a golem of `segments` segments
+    pub fn sample(config: &RandomizationConfig, segments: usize, rng: &mut Rng) -> Self {
+        let mut draw = |[min, max]: [f32; 2]| rng.range(min, max);
+        Self {
+            densities: (0..segments).map(|_| draw(config.density)).collect(),
+            friction: draw(config.friction),
+            motor_stiffness: draw(config.motor_stiffness),
+            motor_damping: draw(config.motor_damping),
+            gravity: draw(config.gravity),
+            ground_height: draw(config.ground_height),
+        }
+    }
+
+    /// Mass and contact properties of golem segment `index`
+    pub(super) fn segment(&self, index: usize) -> (ColliderMassProperties, Friction) {
+        (
+            ColliderMassProperties::Density(
+                self.densities
+                    .get(index)
+                    .copied()
+                    .unwrap_or(NOMINAL_DENSITY),
+            ),
+            Friction::new(self.friction),
+        )
+    }
+}
+
+pub(super) fn apply_gravity(
+    params: Res<PhysicsParams>,
+    mut query: Query<&mut RapierConfiguration>,
+) {
+    for mut config in query.iter_mut() {
+        config.gravity = Vec3::NEG_Y * params.gravity;
+    }
+}
@@ -0,0 +1,160 @@
+//! Named task variants, instantiated by name from the command line, the server and tests.
+//!
+//! Every entry builds a complete [`EnvConfig`]: terrain, reward weights, termination and
+//! goals. Names carry a version suffix, bumped whenever a change to an entry would make
+//! results obtained on it incomparable.
+
+use super::env::EnvConfig;
+use super::episode::{EpisodeConfig, SuccessCriterion};
+use super::goal::GoalConfig;
+use super::sensor::TerrainSensorConfig;
+use super::terrain::Terrain;
+
+/// A registered task variant
+pub struct EnvSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    make: fn() -> EnvConfig,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown environment {name}, registered: {registered}")]
+pub struct UnknownEnv {
+    name: String,
+    registered: String,
+}
+
+/// Task variants by name, [`Registry::default`] holding the built-in ones
+pub struct Registry {
+    specs: Vec<EnvSpec>,
+}
+
+impl Registry {
+    /// Registers `make` under `name`, replacing any entry of the same name
+    pub fn register(
+        mut self,
+        name: &'static str,
+        description: &'static str,
+        make: fn() -> EnvConfig,
+    ) -> Self {
+        self.specs.retain(|spec| spec.name != name);
+        self.specs.push(EnvSpec {
+            name,
+            description,
+            make,
+        });
+        self
+    }
+
+    pub fn specs(&self) -> &[EnvSpec] {
+        &self.specs
+    }
+
+    /// Settings of the task registered under `name`
+    pub fn make(&self, name: &str) -> Result<EnvConfig, UnknownEnv> {
+        self.specs
+            .iter()
+            .find(|spec| spec.name == name)
+            .map(|spec| (spec.make)())
+            .ok_or_else(|| UnknownEnv {
+                name: name.to_string(),
+                registered: self
+                    .specs
+                    .iter()
+                    .map(|spec| spec.name)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self { specs: Vec::new() }
+            .register(
+                "golem-walk-v0",
+                "walk along +Z on flat ground",
+                EnvConfig::default,
+            )
+            .register(
+                "golem-goal-v0",
+                "walk to goals drawn 2 to 6 units away",
+                || EnvConfig::goal_reaching(GoalConfig::default()),
+            )
+            .register(
+                "golem-climb-v0",
+                "walk along +Z up a staircase of 20 cm steps, sensing them with rays",
+                || {
+                    let terrain = Terrain::Steps {
+                        start: 2.0,
+                        height: 0.2,
+                        depth: 1.5,
+                        count: 10,
+                    };
+                    EnvConfig {
+                        sensor: Some(TerrainSensorConfig::default()),
+                        episode: EpisodeConfig {
+                            max_steps: 1500,
+                            // golems start at the origin, facing the staircase
+                            success: SuccessCriterion::Distance(terrain.plateau()),
+                            ..EnvConfig::default().episode
+                        },
+                        terrain,
+                        ..EnvConfig::default()
+                    }
+                },
+            )
+            .register(
+                "golem-slope-v0",
+                "walk along +Z up a 10° ramp, sensing it with rays",
+                || {
+                    let terrain = Terrain::Slope {
+                        start: 2.0,
+                        angle: 10f32.to_radians(),
+                        length: 10.0,
+                    };
+                    EnvConfig {
+                        sensor: Some(TerrainSensorConfig::default()),
+                        episode: EpisodeConfig {
+                            max_steps: 1500,
+                            success: SuccessCriterion::Distance(terrain.plateau()),
+                            ..EnvConfig::default().episode
+                        },
+                        terrain,
+                        ..EnvConfig::default()
+                    }
+                },
+            )
+            .register(
+                "golem-turn-v0",
+                "turn in place to face headings drawn at random",
+                // goals drawn under the head, walking away from them only costs reward
+                || {
+                    EnvConfig::goal_reaching(GoalConfig {
+                        distance: [0.0, 0.0],
+                        heading: true,
+                        ..GoalConfig::default()
+                    })
+                },
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::env::GolemEnv;
+
+    #[test]
+    fn every_entry_observes_its_observation_size() {
+        let registry = Registry::default();
+        for spec in registry.specs() {
+            let config = registry.make(spec.name).unwrap();
+            let size = config.observation_size();
+            let mut env = GolemEnv::new(config);
+            assert_eq!(env.reset().len(), size, "{}", spec.name);
+            let step = env.step(&vec![0.0; env.action_size()]);
+            assert_eq!(step.observation.len(), size, "{}", spec.name);
+        }
+    }
+}
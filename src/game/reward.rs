@@ -0,0 +1,527 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::goal::Goal;
+use super::imitation::{Imitation, ReferenceFrame};
+use super::randomization::PhysicsParams;
+use super::{Golem, GolemImpluseMovement, GolemJointState, GolemSensorSet};
+
+/// Snapshot of a golem used to score a single physics step
+pub struct RewardContext {
+    /// Duration of the step in seconds
+    pub dt: f32,
+    pub head: Transform,
+    /// Height of the ground collider center, [`PhysicsParams::ground_height`]
+    pub ground_height: f32,
+    /// Head translation before the step
+    pub previous_head: Vec3,
+    /// Head rotation before the step
+    pub previous_rotation: Quat,
+    /// Joint states in limb order
+    pub joints: Vec<GolemJointState>,
+    /// Goal the golem is chasing, if any
+    pub goal: Option<Goal>,
+    /// Reference pose the golem is imitating, if any
+    pub reference: Option<ReferenceFrame>,
+}
+
+/// A single reward term, weighted and summed by [`RewardFunction`]
+pub trait RewardFn: Send + Sync + 'static {
+    /// Name used when logging the term
+    fn name(&self) -> &'static str;
+    fn compute(&self, ctx: &RewardContext) -> f32;
+}
+
+/// Displacement of the head along `direction` during the step
+pub struct ForwardProgress {
+    pub direction: Vec3,
+}
+
+impl RewardFn for ForwardProgress {
+    fn name(&self) -> &'static str {
+        "forward_progress"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        (ctx.head.translation - ctx.previous_head).dot(self.direction.normalize_or_zero())
+    }
+}
+
+/// Reduction of the horizontal distance between the head and its goal during the step,
+/// 0 for golems without a goal
+pub struct GoalProgress;
+
+impl RewardFn for GoalProgress {
+    fn name(&self) -> &'static str {
+        "goal_progress"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.goal.map_or(0.0, |goal| {
+            goal.distance(ctx.previous_head) - goal.distance(ctx.head.translation)
+        })
+    }
+}
+
+/// Reduction of the heading error towards the goal during the step, 0 for golems without
+/// a goal heading
+pub struct HeadingProgress;
+
+impl RewardFn for HeadingProgress {
+    fn name(&self) -> &'static str {
+        "heading_progress"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.goal.map_or(0.0, |goal| {
+            goal.heading_error(ctx.previous_rotation).abs()
+                - goal.heading_error(ctx.head.rotation).abs()
+        })
+    }
+}
+
+/// 1 on the step a golem reaches its goal
+pub struct GoalReached;
+
+impl RewardFn for GoalReached {
+    fn name(&self) -> &'static str {
+        "goal_reached"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.goal.map_or(0.0, |goal| goal.reached as u8 as f32)
+    }
+}
+
+/// Tracking of the reference joint positions, 1 on the reference pose and falling off
+/// with the squared error, 0 for golems imitating nothing
+pub struct ImitationPose {
+    pub scale: f32,
+}
+
+impl RewardFn for ImitationPose {
+    fn name(&self) -> &'static str {
+        "imitation_pose"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.reference.as_ref().map_or(0.0, |reference| {
+            let error: f32 = ctx
+                .joints
+                .iter()
+                .zip(&reference.positions)
+                .map(|(joint, position)| (joint.position - position).powi(2))
+                .sum();
+            (-self.scale * error).exp() * ctx.dt
+        })
+    }
+}
+
+/// Tracking of the reference joint velocities, like [`ImitationPose`]
+pub struct ImitationVelocity {
+    pub scale: f32,
+}
+
+impl RewardFn for ImitationVelocity {
+    fn name(&self) -> &'static str {
+        "imitation_velocity"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.reference.as_ref().map_or(0.0, |reference| {
+            let error: f32 = ctx
+                .joints
+                .iter()
+                .zip(&reference.velocities)
+                .map(|(joint, velocity)| (joint.velocity - velocity).powi(2))
+                .sum();
+            (-self.scale * error).exp() * ctx.dt
+        })
+    }
+}
+
+/// 1 when the head stands straight, falling to 0 once it lies on its side
+pub struct Upright;
+
+impl RewardFn for Upright {
+    fn name(&self) -> &'static str {
+        "upright"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.head.up().dot(Vec3::Y).max(0.0) * ctx.dt
+    }
+}
+
+/// Mechanical work spent by the joint motors
+pub struct Energy;
+
+impl RewardFn for Energy {
+    fn name(&self) -> &'static str {
+        "energy"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.joints
+            .iter()
+            .map(|joint| (joint.effort * joint.velocity).abs() * ctx.dt)
+            .sum()
+    }
+}
+
+/// 1 per second while the head is below `height` above the ground
+pub struct Fall {
+    pub height: f32,
+}
+
+impl RewardFn for Fall {
+    fn name(&self) -> &'static str {
+        "fall"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        if ctx.head.translation.y - ctx.ground_height < self.height {
+            ctx.dt
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Grows from 0 to 1 per second as a joint enters the last `margin` fraction of its range
+pub struct JointLimit {
+    pub margin: f32,
+}
+
+impl RewardFn for JointLimit {
+    fn name(&self) -> &'static str {
+        "joint_limit"
+    }
+
+    fn compute(&self, ctx: &RewardContext) -> f32 {
+        ctx.joints
+            .iter()
+            .map(|joint| {
+                let range = joint.limits[1] - joint.limits[0];
+                if range <= 0.0 || self.margin <= 0.0 {
+                    return 0.0;
+                }
+                let u = (joint.position - joint.limits[0]) / range;
+                let distance = u.min(1.0 - u);
+                ((self.margin - distance) / self.margin).clamp(0.0, 1.0) * ctx.dt
+            })
+            .sum()
+    }
+}
+
+/// Weights of the built-in reward terms, a weight of 0 drops the term
+#[derive(Resource, Clone, Debug)]
+pub struct RewardConfig {
+    pub forward_progress: f32,
+    pub upright: f32,
+    pub energy: f32,
+    pub fall: f32,
+    pub joint_limit: f32,
+    pub goal_progress: f32,
+    pub goal_reached: f32,
+    pub heading_progress: f32,
+    pub imitation_pose: f32,
+    pub imitation_velocity: f32,
+    /// Direction the golem is rewarded for walking along
+    pub forward: Vec3,
+    /// Head height above the ground collider center under which the golem counts as fallen
+    pub fall_height: f32,
+    /// Fraction of the joint range penalized near each limit
+    pub joint_limit_margin: f32,
+    /// Sharpness of the pose tracking term, per squared unit of joint offset
+    pub imitation_pose_scale: f32,
+    /// Sharpness of the velocity tracking term, per squared unit of joint velocity
+    pub imitation_velocity_scale: f32,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        Self {
+            forward_progress: 1.0,
+            upright: 0.1,
+            energy: -0.001,
+            fall: -60.0,
+            joint_limit: -0.6,
+            goal_progress: 1.0,
+            goal_reached: 1.0,
+            heading_progress: 1.0,
+            imitation_pose: 1.0,
+            imitation_velocity: 0.1,
+            forward: Vec3::Z,
+            fall_height: 1.0,
+            joint_limit_margin: 0.05,
+            imitation_pose_scale: 5.0,
+            imitation_velocity_scale: 0.1,
+        }
+    }
+}
+
+/// Weighted sum of reward terms
+#[derive(Resource, Default)]
+pub struct RewardFunction {
+    terms: Vec<(f32, Box<dyn RewardFn>)>,
+}
+
+impl RewardFunction {
+    pub fn from_config(config: &RewardConfig) -> Self {
+        Self::default()
+            .with(
+                config.forward_progress,
+                ForwardProgress {
+                    direction: config.forward,
+                },
+            )
+            .with(config.upright, Upright)
+            .with(config.energy, Energy)
+            .with(
+                config.fall,
+                Fall {
+                    height: config.fall_height,
+                },
+            )
+            .with(
+                config.joint_limit,
+                JointLimit {
+                    margin: config.joint_limit_margin,
+                },
+            )
+            .with(config.goal_progress, GoalProgress)
+            .with(config.goal_reached, GoalReached)
+            .with(config.heading_progress, HeadingProgress)
+            .with(
+                config.imitation_pose,
+                ImitationPose {
+                    scale: config.imitation_pose_scale,
+                },
+            )
+            .with(
+                config.imitation_velocity,
+                ImitationVelocity {
+                    scale: config.imitation_velocity_scale,
+                },
+            )
+    }
+
+    /// Adds a term, ignored when `weight` is 0
+    pub fn with(mut self, weight: f32, term: impl RewardFn) -> Self {
+        if weight != 0.0 {
+            self.terms.push((weight, Box::new(term)));
+        }
+        self
+    }
+
+    /// Weighted value of every term, in insertion order
+    pub fn evaluate(&self, ctx: &RewardContext) -> Vec<(&'static str, f32)> {
+        self.terms
+            .iter()
+            .map(|(weight, term)| (term.name(), weight * term.compute(ctx)))
+            .collect()
+    }
+}
+
+/// Reward earned by a golem during the last physics step
+#[derive(Component, Clone, Default, Debug)]
+pub struct StepReward {
+    pub total: f32,
+    /// Weighted value of each term, summing to `total`
+    pub terms: Vec<(&'static str, f32)>,
+    previous_head: Option<Transform>,
+}
+
+impl StepReward {
+    /// Forgets the previous head position, e.g. after teleporting the golem
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+fn init_reward_function(mut cmd: Commands, config: Res<RewardConfig>) {
+    cmd.insert_resource(RewardFunction::from_config(&config));
+}
+
+type RewardedGolem<'a> = (
+    Entity,
+    &'a Transform,
+    Option<&'a Goal>,
+    Option<&'a Imitation>,
+    &'a mut StepReward,
+);
+
+pub(super) fn compute_reward(
+    time: Res<Time>,
+    params: Res<PhysicsParams>,
+    reward_fn: Res<RewardFunction>,
+    joints: Query<(&ImpulseJoint, &GolemImpluseMovement, &GolemJointState)>,
+    mut query: Query<RewardedGolem, With<Golem>>,
+) {
+    for (e, transform, goal, imitation, mut reward) in query.iter_mut() {
+        let previous_head = reward.previous_head.unwrap_or(*transform);
+        reward.previous_head = Some(*transform);
+
+        let mut limbs: Vec<_> = joints
+            .iter()
+            .filter(|(joint, _, _)| joint.parent == e)
+            .map(|(_, mov, state)| (mov.index, *state))
+            .collect();
+        limbs.sort_by_key(|(index, _)| *index);
+        let ctx = RewardContext {
+            dt: time.delta_secs(),
+            head: *transform,
+            ground_height: params.ground_height,
+            previous_head: previous_head.translation,
+            previous_rotation: previous_head.rotation,
+            joints: limbs.into_iter().map(|(_, state)| state).collect(),
+            goal: goal.copied(),
+            reference: imitation.map(|imitation| imitation.target.clone()),
+        };
+        reward.terms = reward_fn.evaluate(&ctx);
+        reward.total = reward.terms.iter().map(|(_, value)| value).sum();
+
+        for (name, value) in reward.terms.iter() {
+            debug!("reward {name}: {value}");
+        }
+        debug!("reward total: {}", reward.total);
+    }
+}
+
+/// Scores every golem after each physics step
+pub struct RewardPlugin;
+impl Plugin for RewardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RewardConfig>()
+            .add_systems(PreStartup, init_reward_function)
+            .add_systems(PostUpdate, compute_reward.after(GolemSensorSet));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.5;
+
+    fn joint(position: f32, velocity: f32, effort: f32) -> GolemJointState {
+        GolemJointState {
+            position,
+            velocity,
+            limits: [0.0, 1.0],
+            effort,
+        }
+    }
+
+    /// Golem standing still at the origin
+    fn context() -> RewardContext {
+        RewardContext {
+            dt: DT,
+            head: Transform::IDENTITY,
+            ground_height: -2.0,
+            previous_head: Vec3::ZERO,
+            previous_rotation: Quat::IDENTITY,
+            joints: vec![joint(0.5, 0.0, 0.0)],
+            goal: None,
+            reference: None,
+        }
+    }
+
+    #[test]
+    fn progress_terms_follow_the_head() {
+        let ctx = RewardContext {
+            head: Transform::from_xyz(0.0, 0.0, 0.5),
+            goal: Some(Goal {
+                position: Vec3::new(0.0, 0.0, 3.0),
+                heading: None,
+                reached: true,
+            }),
+            ..context()
+        };
+        let forward = ForwardProgress {
+            direction: Vec3::Z * 2.0,
+        };
+        assert_eq!(forward.compute(&ctx), 0.5);
+        assert_eq!(GoalProgress.compute(&ctx), 0.5);
+        assert_eq!(GoalReached.compute(&ctx), 1.0);
+        assert_eq!(HeadingProgress.compute(&ctx), 0.0);
+        assert_eq!(GoalProgress.compute(&context()), 0.0);
+    }
+
+    #[test]
+    fn rate_terms_scale_with_the_step_duration() {
+        assert_eq!(Upright.compute(&context()), DT);
+        let lying = RewardContext {
+            head: Transform::from_rotation(Quat::from_rotation_x(std::f32::consts::FRAC_PI_2)),
+            ..context()
+        };
+        assert!(Upright.compute(&lying).abs() < 1e-6);
+
+        let working = RewardContext {
+            joints: vec![joint(0.5, -3.0, 2.0), joint(0.5, 1.0, 1.0)],
+            ..context()
+        };
+        assert_eq!(Energy.compute(&working), 7.0 * DT);
+
+        // measured from the ground, wherever it lies
+        let fall = Fall { height: 1.0 };
+        assert_eq!(fall.compute(&context()), 0.0);
+        let fallen = RewardContext {
+            head: Transform::from_xyz(0.0, -1.5, 0.0),
+            ..context()
+        };
+        assert_eq!(fall.compute(&fallen), DT);
+        let sunken = RewardContext {
+            ground_height: -0.5,
+            ..context()
+        };
+        assert_eq!(fall.compute(&sunken), DT);
+    }
+
+    #[test]
+    fn joint_limit_grows_across_the_margin() {
+        let limit = JointLimit { margin: 0.2 };
+        let at = |position| {
+            limit.compute(&RewardContext {
+                joints: vec![joint(position, 0.0, 0.0)],
+                ..context()
+            })
+        };
+        assert_eq!(at(0.5), 0.0);
+        assert_eq!(at(0.0), DT);
+        assert_eq!(at(1.0), DT);
+        assert!((at(0.9) - 0.5 * DT).abs() < 1e-6);
+    }
+
+    #[test]
+    fn imitation_terms_peak_on_the_reference() {
+        let mut ctx = RewardContext {
+            joints: vec![joint(0.5, 1.0, 0.0)],
+            reference: Some(ReferenceFrame {
+                positions: vec![0.5],
+                velocities: vec![1.0],
+            }),
+            ..context()
+        };
+        let pose = ImitationPose { scale: 5.0 };
+        let velocity = ImitationVelocity { scale: 0.1 };
+        assert_eq!(pose.compute(&ctx), DT);
+        assert_eq!(velocity.compute(&ctx), DT);
+        ctx.joints[0].position = 0.7;
+        assert!((pose.compute(&ctx) - (-5.0f32 * 0.04).exp() * DT).abs() < 1e-6);
+        assert_eq!(pose.compute(&context()), 0.0);
+    }
+
+    #[test]
+    fn function_weights_terms_and_drops_zero_weights() {
+        let function = RewardFunction::default()
+            .with(2.0, Upright)
+            .with(0.0, Energy)
+            .with(-1.0, Fall { height: 10.0 });
+        assert_eq!(
+            function.evaluate(&context()),
+            [("upright", 2.0 * DT), ("fall", -DT)]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::{Golem, GolemSensorSet};
+
+/// Grid of rays cast straight down around every golem, measuring the terrain under and
+/// ahead of it like a height map
+///
+/// Rays start level with the head, laid out in its yaw frame so that the grid turns with
+/// the golem but does not tilt with it. Only fixed colliders are hit: the ground, terrain
+/// features and obstacles, never golems.
+#[derive(Resource, Clone, Debug)]
+pub struct TerrainSensorConfig {
+    /// Rows of rays along the facing direction
+    pub rows: usize,
+    /// Rays in each row, across the facing direction
+    pub columns: usize,
+    /// Distance between neighbouring rays
+    pub spacing: f32,
+    /// Distance ahead of the head of the first row, negative to start behind it
+    pub start: f32,
+    /// Length of the rays, reported by those hitting nothing
+    pub max_distance: f32,
+}
+
+impl Default for TerrainSensorConfig {
+    fn default() -> Self {
+        Self {
+            rows: 5,
+            columns: 3,
+            spacing: 0.5,
+            start: 0.0,
+            max_distance: 5.0,
+        }
+    }
+}
+
+impl TerrainSensorConfig {
+    pub fn rays(&self) -> usize {
+        self.rows * self.columns
+    }
+
+    /// Ray origins relative to the head in its yaw frame, row by row from the nearest one,
+    /// each row running from +X to -X
+    fn origins(&self) -> impl Iterator<Item = Vec3> + '_ {
+        let half_width = (self.columns.max(1) - 1) as f32 * self.spacing / 2.0;
+        (0..self.rows).flat_map(move |row| {
+            (0..self.columns).map(move |column| {
+                Vec3::new(
+                    half_width - column as f32 * self.spacing,
+                    0.0,
+                    self.start + row as f32 * self.spacing,
+                )
+            })
+        })
+    }
+}
+
+/// Distances from the head level down to the terrain measured by a golem's rays, in the
+/// order of [`TerrainSensorConfig`]
+///
+/// Colliders only join the physics world during its first step, until then every ray
+/// reads `max_distance`.
+#[derive(Component, Clone, Debug, Default)]
+pub struct TerrainSensor(pub Vec<f32>);
+
+fn assign_terrain_sensors(
+    mut cmd: Commands,
+    golems: Query<(Entity, Has<TerrainSensor>), With<Golem>>,
+) {
+    for (golem, _) in golems.iter().filter(|(_, sensing)| !sensing) {
+        cmd.entity(golem).insert(TerrainSensor::default());
+    }
+}
+
+fn update_terrain_sensors(
+    config: Res<TerrainSensorConfig>,
+    rapier: ReadRapierContext,
+    mut golems: Query<(&Transform, &mut TerrainSensor), With<Golem>>,
+) {
+    let Ok(rapier) = rapier.single() else {
+        return;
+    };
+    for (transform, mut sensor) in golems.iter_mut() {
+        let forward = transform.rotation * Vec3::Z;
+        let yaw = Quat::from_rotation_y(forward.x.atan2(forward.z));
+        sensor.0 = config
+            .origins()
+            .map(|origin| {
+                rapier
+                    .cast_ray(
+                        transform.translation + yaw * origin,
+                        Vec3::NEG_Y,
+                        config.max_distance,
+                        true,
+                        QueryFilter::only_fixed(),
+                    )
+                    .map_or(config.max_distance, |(_, distance)| distance)
+            })
+            .collect();
+    }
+}
+
+/// Casts the terrain rays of the golems of worlds holding a [`TerrainSensorConfig`]
+pub struct TerrainSensorPlugin;
+impl Plugin for TerrainSensorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostStartup,
+            assign_terrain_sensors.run_if(resource_exists::<TerrainSensorConfig>),
+        )
+        .add_systems(
+            PostUpdate,
+            update_terrain_sensors
+                .run_if(resource_exists::<TerrainSensorConfig>)
+                .in_set(GolemSensorSet)
+                .before(super::observation::update_observation),
+        );
+    }
+}
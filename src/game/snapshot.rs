@@ -0,0 +1,196 @@
+//! Copies of a whole golem world, restored exactly to rewind or branch a simulation.
+//!
+//! A [`Snapshot`] holds the rapier context (bodies, colliders, joints with their motor
+//! targets, contacts and islands) next to the golem components read and written by the
+//! golem systems. Restoring it into the world it was taken from puts every entity back
+//! to that frame, so stepping on with the same actions replays the same trajectory.
+//!
+//! Entities are matched by id: spawning or despawning bodies between the capture and the
+//! restore is not supported.
+
+use bevy::ecs::component::Mutable;
+use bevy::prelude::*;
+use bevy_rapier3d::plugin::context::{
+    RapierContextColliders, RapierContextJoints, RapierContextSimulation, RapierQueryPipeline,
+    RapierRigidBodySet, SimulationToRenderTime,
+};
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{
+    CCDSolver, DefaultBroadPhase, IntegrationParameters, IslandManager, NarrowPhase,
+};
+
+use super::episode::Episode;
+use super::goal::{Goal, GoalSampler};
+use super::imitation::Imitation;
+use super::observation::Observation;
+use super::reward::StepReward;
+use super::{GolemImpluseMovement, GolemJointState};
+use crate::rng::Rng;
+
+/// Persistent part of [`RapierContextSimulation`], its other fields only live during a step
+#[derive(Clone)]
+struct Simulation {
+    islands: IslandManager,
+    broad_phase: DefaultBroadPhase,
+    narrow_phase: NarrowPhase,
+    ccd_solver: CCDSolver,
+    integration_parameters: IntegrationParameters,
+}
+
+/// Rapier state of a physics context entity
+#[derive(Clone)]
+struct Context {
+    entity: Entity,
+    simulation: Simulation,
+    bodies: RapierRigidBodySet,
+    colliders: RapierContextColliders,
+    joints: RapierContextJoints,
+    queries: RapierQueryPipeline,
+    render_time: SimulationToRenderTime,
+    configuration: RapierConfiguration,
+}
+
+/// Components of a simulated entity, `None` for those it does not have
+#[derive(Clone)]
+struct Parts {
+    entity: Entity,
+    transform: Option<Transform>,
+    global_transform: Option<GlobalTransform>,
+    velocity: Option<Velocity>,
+    joint: Option<ImpulseJoint>,
+    movement: Option<GolemImpluseMovement>,
+    joint_state: Option<GolemJointState>,
+    reward: Option<StepReward>,
+    episode: Option<Episode>,
+    observation: Option<Observation>,
+    goal: Option<Goal>,
+    imitation: Option<Imitation>,
+}
+
+/// Full state of a golem world at one frame
+#[derive(Clone)]
+pub struct Snapshot {
+    contexts: Vec<Context>,
+    entities: Vec<Parts>,
+    goal_rng: Option<Rng>,
+}
+
+fn get<C: Component + Clone>(world: &World, entity: Entity) -> Option<C> {
+    world.get::<C>(entity).cloned()
+}
+
+fn set<C: Component<Mutability = Mutable> + Clone>(
+    world: &mut World,
+    entity: Entity,
+    value: Option<&C>,
+) {
+    if let Some(value) = value
+        && let Some(mut component) = world.get_mut::<C>(entity)
+    {
+        *component = value.clone();
+    }
+}
+
+impl Snapshot {
+    pub fn capture(world: &mut World) -> Self {
+        let mut query = world.query::<(Entity, &RapierContextSimulation)>();
+        let simulations: Vec<_> = query
+            .iter(world)
+            .map(|(entity, simulation)| {
+                let simulation = Simulation {
+                    islands: simulation.islands.clone(),
+                    broad_phase: simulation.broad_phase.clone(),
+                    narrow_phase: simulation.narrow_phase.clone(),
+                    ccd_solver: simulation.ccd_solver.clone(),
+                    integration_parameters: simulation.integration_parameters,
+                };
+                (entity, simulation)
+            })
+            .collect();
+        let contexts = simulations
+            .into_iter()
+            .filter_map(|(entity, simulation)| {
+                Some(Context {
+                    entity,
+                    simulation,
+                    bodies: get(world, entity)?,
+                    colliders: get(world, entity)?,
+                    joints: get(world, entity)?,
+                    queries: get(world, entity)?,
+                    render_time: get(world, entity)?,
+                    configuration: get(world, entity)?,
+                })
+            })
+            .collect();
+
+        let mut query = world.query_filtered::<Entity, Or<(With<RigidBody>, With<Collider>)>>();
+        let entities: Vec<Entity> = query.iter(world).collect();
+        let entities = entities
+            .into_iter()
+            .map(|entity| Parts {
+                entity,
+                transform: get(world, entity),
+                global_transform: get(world, entity),
+                velocity: get(world, entity),
+                joint: get(world, entity),
+                movement: get(world, entity),
+                joint_state: get(world, entity),
+                reward: get(world, entity),
+                episode: get(world, entity),
+                observation: get(world, entity),
+                goal: get(world, entity),
+                imitation: get(world, entity),
+            })
+            .collect();
+
+        Self {
+            contexts,
+            entities,
+            goal_rng: world
+                .get_resource::<GoalSampler>()
+                .map(|sampler| sampler.rng.clone()),
+        }
+    }
+
+    /// Puts the world it was captured from back to the captured frame
+    pub fn restore(&self, world: &mut World) {
+        for context in &self.contexts {
+            let entity = context.entity;
+            if let Some(mut simulation) = world.get_mut::<RapierContextSimulation>(entity) {
+                let state = context.simulation.clone();
+                simulation.islands = state.islands;
+                simulation.broad_phase = state.broad_phase;
+                simulation.narrow_phase = state.narrow_phase;
+                simulation.ccd_solver = state.ccd_solver;
+                simulation.integration_parameters = state.integration_parameters;
+            }
+            set(world, entity, Some(&context.bodies));
+            set(world, entity, Some(&context.colliders));
+            set(world, entity, Some(&context.joints));
+            set(world, entity, Some(&context.queries));
+            set(world, entity, Some(&context.render_time));
+            set(world, entity, Some(&context.configuration));
+        }
+
+        for parts in &self.entities {
+            let entity = parts.entity;
+            set(world, entity, parts.transform.as_ref());
+            set(world, entity, parts.global_transform.as_ref());
+            set(world, entity, parts.velocity.as_ref());
+            set(world, entity, parts.joint.as_ref());
+            set(world, entity, parts.movement.as_ref());
+            set(world, entity, parts.joint_state.as_ref());
+            set(world, entity, parts.reward.as_ref());
+            set(world, entity, parts.episode.as_ref());
+            set(world, entity, parts.observation.as_ref());
+            set(world, entity, parts.goal.as_ref());
+            set(world, entity, parts.imitation.as_ref());
+        }
+
+        if let Some(rng) = &self.goal_rng
+            && let Some(mut sampler) = world.get_resource_mut::<GoalSampler>()
+        {
+            sampler.rng = rng.clone();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+/// Half width of the terrain features, across the walking direction
+const FEATURE_HALF_WIDTH: f32 = 10.0;
+
+/// Where features end along +Z, far past anything an episode reaches
+const FEATURE_END: f32 = 100.0;
+
+/// Obstacles laid on top of the ground, every feature rising along +Z
+#[derive(Resource, Clone, Debug, Default)]
+pub enum Terrain {
+    #[default]
+    Flat,
+    /// A staircase of `count` steps `height` tall and `depth` deep starting `start` ahead of
+    /// the origin, its top step running on as a plateau
+    Steps {
+        start: f32,
+        height: f32,
+        depth: f32,
+        count: usize,
+    },
+    /// A ramp rising at `angle` radians over `length` along +Z starting `start` ahead of the
+    /// origin, its top running on as a plateau
+    Slope { start: f32, angle: f32, length: f32 },
+}
+
+impl Terrain {
+    /// Lower features, `level` 1 leaving them as they are and 0 flattening them
+    pub fn scaled(&self, level: f32) -> Self {
+        match *self {
+            Terrain::Steps { height, .. } if height * level <= 0.0 => Terrain::Flat,
+            Terrain::Steps {
+                start,
+                height,
+                depth,
+                count,
+            } => Terrain::Steps {
+                start,
+                height: height * level,
+                depth,
+                count,
+            },
+            Terrain::Slope { angle, .. } if angle * level <= 0.0 => Terrain::Flat,
+            Terrain::Slope {
+                start,
+                angle,
+                length,
+            } => Terrain::Slope {
+                start,
+                angle: angle * level,
+                length,
+            },
+            Terrain::Flat => Terrain::Flat,
+        }
+    }
+
+    /// Distance along +Z at which the features give way to a plateau, 0 on flat ground
+    pub fn plateau(&self) -> f32 {
+        match *self {
+            Terrain::Flat => 0.0,
+            Terrain::Steps {
+                start,
+                depth,
+                count,
+                ..
+            } => start + count.saturating_sub(1) as f32 * depth,
+            Terrain::Slope { start, length, .. } => start + length,
+        }
+    }
+
+    /// Colliders of the features resting on a ground surface at `ground_top`
+    pub(super) fn colliders(&self, ground_top: f32) -> Vec<(Collider, Transform)> {
+        match *self {
+            Terrain::Flat => Vec::new(),
+            Terrain::Steps {
+                start,
+                height,
+                depth,
+                count,
+            } => (0..count)
+                .map(|i| {
+                    // stacked slabs, each one starting a step further
+                    let from = start + i as f32 * depth;
+                    let half_length = (FEATURE_END - from) / 2.0;
+                    (
+                        Collider::cuboid(FEATURE_HALF_WIDTH, height / 2.0, half_length),
+                        Transform::from_xyz(
+                            0.0,
+                            ground_top + (i as f32 + 0.5) * height,
+                            from + half_length,
+                        ),
+                    )
+                })
+                .collect(),
+            Terrain::Slope {
+                start,
+                angle,
+                length,
+            } => {
+                // the ramp and its plateau make a single convex wedge
+                let top = length * angle.tan();
+                let points: Vec<Vec3> = [-FEATURE_HALF_WIDTH, FEATURE_HALF_WIDTH]
+                    .into_iter()
+                    .flat_map(|x| {
+                        [
+                            Vec3::new(x, 0.0, start),
+                            Vec3::new(x, 0.0, FEATURE_END),
+                            Vec3::new(x, top, start + length),
+                            Vec3::new(x, top, FEATURE_END),
+                        ]
+                    })
+                    .collect();
+                Collider::convex_hull(&points)
+                    .map(|collider| (collider, Transform::from_xyz(0.0, ground_top, 0.0)))
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+}
@@ -2,9 +2,275 @@ use bevy::prelude::*;
 use bevy::{
     dev_tools::fps_overlay::{FpsOverlayConfig, FpsOverlayPlugin},
     text::FontSmoothing};
+mod bc;
+mod checkpoint;
+mod controller;
+mod curriculum;
+mod dataset;
+mod es;
+mod eval;
 mod game;
+mod metrics;
+mod mlp;
+mod morphology;
+mod normalize;
+mod optim;
+mod planner;
+mod ppo;
+mod rng;
+mod server;
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // `--env <name>` anywhere selects the registered task, `golem-walk-v0` by default
+    let registry = game::registry::Registry::default();
+    let name = take_option(&mut args, "--env").unwrap_or_else(|| "golem-walk-v0".into());
+    let mut env = match registry.make(&name) {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    // `--curriculum` makes the trainers start from the easiest variant of the task
+    let curriculum = args
+        .iter()
+        .any(|arg| arg == "--curriculum")
+        .then(curriculum::CurriculumConfig::default);
+    args.retain(|arg| arg != "--curriculum");
+    // `--checkpoint <dir>` makes the trainers save and resume their state there, `--run <dir>`
+    // logs their metrics there
+    let checkpoint = take_option(&mut args, "--checkpoint").map(std::path::PathBuf::from);
+    let run = take_option(&mut args, "--run").map(std::path::PathBuf::from);
+    // `--blueprint <file>` swaps the golem body for one saved by `evolve-body`
+    if let Some(path) = take_option(&mut args, "--blueprint") {
+        match game::blueprint::GolemBlueprint::load(&path) {
+            Ok(blueprint) => env.blueprint = blueprint,
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    // `--imitate <dataset>` rewards tracking the joint motion recorded in one episode of a
+    // dataset, the first one unless `--imitate-episode <index>` picks another
+    let imitate_episode = take_option(&mut args, "--imitate-episode")
+        .and_then(|i| i.parse().ok())
+        .unwrap_or(0);
+    if let Some(path) = take_option(&mut args, "--imitate") {
+        let motion = dataset::Demonstrations::read(&path).and_then(|demonstrations| {
+            match demonstrations.episode_observations(imitate_episode) {
+                // datasets hold one observation per environment step, each action being held
+                // for `repeat` physics steps
+                Some(observations) => Ok(game::imitation::ReferenceMotion::from_observations(
+                    env.dt * env.action.repeat.max(1) as f32,
+                    env.joints(),
+                    observations,
+                    true,
+                )),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("no episode {imitate_episode} in the dataset"),
+                )),
+            }
+        });
+        match motion {
+            Ok(motion) => env.imitation = Some(game::imitation::ImitationConfig::new(motion)),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    match args.first().map(String::as_str) {
+        Some("envs") => {
+            for spec in registry.specs() {
+                println!("{:<16} {}", spec.name, spec.description);
+            }
+            return;
+        }
+        Some("bench") => {
+            let envs = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(8);
+            let steps = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(1000);
+            bench(envs, steps, env);
+            return;
+        }
+        Some("record") => {
+            let out = args.get(1).map_or("trajectories.jsonl", String::as_str);
+            let episodes = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(10);
+            let seed = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(0);
+            if let Err(e) = record(out, episodes, seed, env) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("serve") => {
+            let addr = args.get(1).map_or("127.0.0.1:5555", String::as_str);
+            if let Err(e) = server::serve(addr, env) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("client") => {
+            let addr = args.get(1).map_or("127.0.0.1:5555", String::as_str);
+            let steps = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(100);
+            if let Err(e) = client(addr, steps) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("train-es") => {
+            let kind = args.get(1).map_or("cpg", String::as_str);
+            let out = args.get(2).map_or("policy.json", String::as_str);
+            let generations = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(50);
+            let seed = args.get(4).and_then(|n| n.parse().ok()).unwrap_or(0);
+            let env = game::env::EnvConfig { seed, ..env };
+            let (checkpoint, run) = (checkpoint.as_deref(), run.as_deref());
+            if let Err(e) = train_es(kind, out, generations, checkpoint, run, env, curriculum) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("train-ppo") => {
+            let out = args.get(1).map_or("policy.mlp", String::as_str);
+            let iterations = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(100);
+            let seed = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(0);
+            let env = game::env::EnvConfig { seed, ..env };
+            let (checkpoint, run) = (checkpoint.as_deref(), run.as_deref());
+            if let Err(e) = train_ppo(out, iterations, checkpoint, run, env, curriculum) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("eval") => {
+            let spec = args.get(1).map_or("scripted", String::as_str);
+            let episodes = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(20);
+            let seed = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(0);
+            let out = args.get(4).map(String::as_str);
+            if let Err(e) = run_eval(spec, episodes, seed, out, env) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("multi") => {
+            let cooperative = args.get(1).is_some_and(|task| task == "coop");
+            let episodes = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(5);
+            let seed = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(0);
+            let specs: Vec<&str> = args.iter().skip(4).map(String::as_str).collect();
+            if let Err(e) = run_multi(cooperative, episodes, seed, &specs, env) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("evolve-body") => {
+            let out = args.get(1).map_or("blueprint.json", String::as_str);
+            let generations = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(10);
+            let seed = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(0);
+            let env = game::env::EnvConfig { seed, ..env };
+            if let Err(e) = evolve_body(out, generations, env) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("train-bc") => {
+            let dataset = args.get(1).map_or("demonstrations.jsonl", String::as_str);
+            let out = args.get(2).map_or("policy.mlp", String::as_str);
+            let epochs = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(50);
+            let seed = args.get(4).and_then(|n| n.parse().ok()).unwrap_or(0);
+            if let Err(e) = train_bc(dataset, out, epochs, seed, env) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("policy-init") => {
+            let out = args.get(1).map_or("policy.mlp", String::as_str);
+            let hidden = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(64);
+            let seed = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(0);
+            let sizes = [env.observation_size(), hidden, hidden, env.action_size()];
+            let mlp = mlp::Mlp::random(&sizes, mlp::Activation::Tanh, &mut rng::Rng::new(seed));
+            if let Err(e) = mlp.save(out) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
+
     let mut app = App::new();
+    if let Some("play") = args.first().map(String::as_str) {
+        let path = args.get(1).map_or("policy.mlp", String::as_str);
+        match mlp::Mlp::load(path) {
+            Ok(mlp) => {
+                println!(
+                    "playing {path}: {} inputs, {} outputs",
+                    mlp.input_size(),
+                    mlp.output_size()
+                );
+                // every frame is one physics step of the environment's `dt`, like a step of the
+                // headless environments, so joint velocities, rewards and the control rate match
+                // training whatever the frame rate
+                app.insert_resource(bevy_rapier3d::prelude::TimestepMode::Fixed {
+                    dt: env.dt,
+                    substeps: 1,
+                })
+                .insert_resource(bevy::time::TimeUpdateStrategy::ManualDuration(
+                    core::time::Duration::from_secs_f32(env.dt),
+                ))
+                .insert_resource(game::PolicyDriver::new(mlp, env.action.clone()));
+            }
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some("demo") = args.first().map(String::as_str) {
+        let out = args.get(1).map_or("demonstrations.jsonl", String::as_str);
+        match create_writer(out) {
+            Ok(writer) => {
+                println!("recording the golem to {out}");
+                // every frame is one physics step of the environment's `dt`, so the recorded
+                // joint velocities and rewards are those the headless environments observe
+                app.insert_resource(bevy_rapier3d::prelude::TimestepMode::Fixed {
+                    dt: env.dt,
+                    substeps: 1,
+                })
+                .insert_resource(bevy::time::TimeUpdateStrategy::ManualDuration(
+                    core::time::Duration::from_secs_f32(env.dt),
+                ))
+                .insert_resource(game::demo::DemoRecorder::new(writer));
+            }
+            Err(e) => {
+                eprintln!("{out}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(goal) = env.goal {
+        app.insert_resource(game::goal::GoalSampler {
+            config: goal,
+            rng: rng::Rng::new(0),
+        });
+    }
+    if let Some(imitation) = env.imitation {
+        app.insert_resource(imitation);
+    }
+    if let Some(sensor) = env.sensor {
+        app.insert_resource(sensor);
+    }
+    // the game keeps resetting the golem itself, only the task settings carry over
+    app.insert_resource(env.reward)
+        .insert_resource(env.terrain)
+        .insert_resource(env.blueprint);
     app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
         .add_plugins(game::GameModule)
         .add_plugins(FpsOverlayPlugin {
@@ -27,3 +293,434 @@ fn main() {
         })
         .run();
 }
+
+/// Removes `flag` and the value following it from `args`, returning the value
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    let value = args.get(i + 1).cloned();
+    args.drain(i..(i + 2).min(args.len()));
+    value
+}
+
+/// Evaluates the controller `spec` on seeded episodes, printing the JSON report and
+/// writing it to `out` when given
+fn run_eval(
+    spec: &str,
+    episodes: usize,
+    seed: u64,
+    out: Option<&str>,
+    env: game::env::EnvConfig,
+) -> std::io::Result<()> {
+    let mut env = game::env::GolemEnv::new(env);
+    let mut controller = controller::load(spec, env.observation_size(), env.action_size())?;
+    let report = eval::evaluate(&mut env, controller.as_mut(), episodes, seed);
+    let json = serde_json::to_string_pretty(&report)?;
+    println!("{json}");
+    if let Some(out) = out {
+        std::fs::write(out, json)?;
+    }
+    Ok(())
+}
+
+/// Plays seeded multi-agent episodes, one controller `spec` per golem (scripted when
+/// missing), printing each outcome
+///
+/// Single-agent policies only read the prefix of the observation describing their own golem.
+fn run_multi(
+    cooperative: bool,
+    episodes: usize,
+    seed: u64,
+    specs: &[&str],
+    config: game::env::EnvConfig,
+) -> std::io::Result<()> {
+    let task = if cooperative {
+        game::multi::MultiTask::Cooperative {
+            agents: specs.len().max(2),
+            spacing: 4.0,
+        }
+    } else {
+        game::multi::MultiTask::Sumo {
+            ring_radius: 4.0,
+            separation: 4.0,
+        }
+    };
+    // the single-agent part of each observation, what the controllers were made for
+    let (own_size, joints) = (config.observation_size(), config.action_size());
+    let mut env = game::multi::MultiGolemEnv::new(config, task);
+    let mut controllers = (0..env.agents())
+        .map(|i| {
+            let spec = specs.get(i).copied().unwrap_or("scripted");
+            controller::load(spec, own_size, joints)
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    println!(
+        "{} agents observing {} values each",
+        env.agents(),
+        env.observation_size()
+    );
+
+    let mut wins = vec![0; env.agents()];
+    for episode in 0..episodes {
+        env.reseed(seed + episode as u64);
+        let mut observations = env.reset();
+        for controller in &mut controllers {
+            controller.reset();
+        }
+        let mut returns = vec![0.0; env.agents()];
+        let mut length = 0;
+        let step = loop {
+            let actions: Vec<_> = controllers
+                .iter_mut()
+                .zip(&observations)
+                .map(|(controller, observation)| controller.act(&observation[..own_size]))
+                .collect();
+            let step = env.step(&actions);
+            for (total, reward) in returns.iter_mut().zip(&step.rewards) {
+                *total += reward;
+            }
+            length += 1;
+            if step.done() {
+                break step;
+            }
+            observations = step.observations;
+        };
+        let outcome = match step.winner {
+            Some(winner) => {
+                wins[winner] += 1;
+                format!("agent {winner} won")
+            }
+            None if step.terminated => "ended".to_string(),
+            None => "time limit".to_string(),
+        };
+        println!("episode {episode}: {outcome} after {length} steps, returns {returns:.2?}");
+    }
+    if !cooperative {
+        let draws = episodes - wins.iter().sum::<usize>();
+        println!("wins {wins:?}, draws {draws}");
+    }
+    Ok(())
+}
+
+/// Iterations between two checkpoints of a training run
+const CHECKPOINT_INTERVAL: u64 = 10;
+
+/// Runs ES generations until `generations` is reached, resuming from and saving to `checkpoint`
+/// and logging metrics to `run`
+fn run_es<P: controller::Parametric + Clone + Send>(
+    config: es::EsConfig,
+    policy: P,
+    generations: u64,
+    checkpoint: Option<&std::path::Path>,
+    run: Option<&std::path::Path>,
+) -> std::io::Result<P> {
+    let mut trainer = match checkpoint.filter(|dir| checkpoint::exists(dir)) {
+        Some(dir) => es::EsTrainer::resume(config, policy, dir)?,
+        None => es::EsTrainer::new(config, policy),
+    };
+    let mut metrics = run.map(metrics::MetricsLogger::create).transpose()?;
+    while trainer.generation < generations {
+        let start = std::time::Instant::now();
+        let generation = trainer.step();
+        let fps = generation.steps as f32 / start.elapsed().as_secs_f32();
+        let level = generation
+            .level
+            .map_or_else(String::new, |level| format!(", level {level:.1}"));
+        println!(
+            "generation {}: mean return {:.3}, best {:.3}{level}, {fps:.0} steps/s",
+            generation.index, generation.mean_return, generation.best_return
+        );
+        if let Some(metrics) = &mut metrics {
+            metrics.scalar("es/mean_return", generation.index, generation.mean_return)?;
+            metrics.scalar("es/best_return", generation.index, generation.best_return)?;
+            if let Some(level) = generation.level {
+                metrics.scalar("curriculum/level", generation.index, level)?;
+            }
+            metrics.scalar("perf/fps", generation.index, fps)?;
+            metrics.flush()?;
+        }
+        if let Some(dir) = checkpoint
+            && (generation.index.is_multiple_of(CHECKPOINT_INTERVAL)
+                || generation.index == generations)
+        {
+            trainer.save_checkpoint(dir)?;
+        }
+    }
+    Ok(trainer.policy)
+}
+
+/// Tunes a CPG (saved as JSON) or an MLP (saved as a weight file) with evolution strategies,
+/// resuming from `checkpoint` when it holds a saved run
+fn train_es(
+    kind: &str,
+    out: &str,
+    generations: u64,
+    checkpoint: Option<&std::path::Path>,
+    run: Option<&std::path::Path>,
+    env: game::env::EnvConfig,
+    curriculum: Option<curriculum::CurriculumConfig>,
+) -> std::io::Result<()> {
+    let seed = env.seed;
+    let config = es::EsConfig {
+        env,
+        curriculum,
+        ..default()
+    };
+    let (observation_size, action_size) = (config.env.observation_size(), config.env.action_size());
+    match kind {
+        "cpg" => {
+            let cpg = controller::CpgController::new(action_size, config.env.dt);
+            let cpg = run_es(config, cpg, generations, checkpoint, run)?;
+            std::fs::write(out, serde_json::to_string_pretty(&cpg)?)
+        }
+        "mlp" => {
+            let sizes = [observation_size, 32, action_size];
+            let mlp = mlp::Mlp::random(&sizes, mlp::Activation::Tanh, &mut rng::Rng::new(seed));
+            let policy = controller::MlpController {
+                mlp,
+                name: "mlp-es".into(),
+            };
+            run_es(config, policy, generations, checkpoint, run)?
+                .mlp
+                .save(out)
+        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("unknown policy kind {kind}, expected cpg or mlp"),
+        )),
+    }
+}
+
+/// Trains a policy with PPO and saves the mean network as a weight file the game can play,
+/// resuming from `checkpoint` when it holds a saved run and logging metrics to `run`
+fn train_ppo(
+    out: &str,
+    iterations: u64,
+    checkpoint: Option<&std::path::Path>,
+    run: Option<&std::path::Path>,
+    env: game::env::EnvConfig,
+    curriculum: Option<curriculum::CurriculumConfig>,
+) -> std::io::Result<()> {
+    let config = ppo::PpoConfig {
+        env,
+        curriculum,
+        ..default()
+    };
+    let mut trainer = match checkpoint.filter(|dir| checkpoint::exists(dir)) {
+        Some(dir) => ppo::PpoTrainer::resume(config, dir)?,
+        None => ppo::PpoTrainer::new(config),
+    };
+    let mut metrics = run.map(metrics::MetricsLogger::create).transpose()?;
+    while trainer.iteration < iterations {
+        let (start, steps) = (std::time::Instant::now(), trainer.total_steps);
+        let it = trainer.step();
+        let fps = (it.total_steps - steps) as f32 / start.elapsed().as_secs_f32();
+        let level = it
+            .level
+            .map_or_else(String::new, |level| format!(", level {level:.1}"));
+        println!(
+            "iteration {} ({} steps): {} episodes, mean return {:.3}, policy loss {:.4}, value loss {:.4}, entropy {:.3}{level}, {fps:.0} steps/s",
+            it.index,
+            it.total_steps,
+            it.episodes,
+            it.mean_return,
+            it.policy_loss,
+            it.value_loss,
+            it.entropy
+        );
+        if let Some(metrics) = &mut metrics {
+            let step = it.total_steps;
+            if it.episodes > 0 {
+                metrics.scalar("ppo/mean_return", step, it.mean_return)?;
+            }
+            metrics.scalar("ppo/policy_loss", step, it.policy_loss)?;
+            metrics.scalar("ppo/value_loss", step, it.value_loss)?;
+            metrics.scalar("ppo/entropy", step, it.entropy)?;
+            if let Some(level) = it.level {
+                metrics.scalar("curriculum/level", step, level)?;
+            }
+            metrics.scalar("perf/fps", step, fps)?;
+            metrics.flush()?;
+        }
+        if let Some(dir) = checkpoint
+            && (it.index.is_multiple_of(CHECKPOINT_INTERVAL) || it.index == iterations)
+        {
+            trainer.save_checkpoint(dir)?;
+        }
+    }
+    trainer.policy.save(out)
+}
+
+/// Evolves golem bodies from the one of `env`, saving the fittest blueprint to `out` and
+/// its tuned gait next to it
+fn evolve_body(out: &str, generations: u64, env: game::env::EnvConfig) -> std::io::Result<()> {
+    let config = morphology::MorphologyConfig {
+        es: es::EsConfig { env, ..default() },
+        ..default()
+    };
+    let mut search = morphology::MorphologySearch::new(config);
+    while search.generation < generations {
+        let generation = search.step();
+        let best = &generation.best;
+        println!(
+            "generation {}: mean fitness {:.3}, best {:.3} with {} limbs ({:016x})",
+            generation.index,
+            generation.mean_fitness,
+            best.fitness,
+            best.blueprint.limbs.len(),
+            best.blueprint.hash()
+        );
+    }
+    let best = &search.population[0];
+    best.blueprint.save(out)?;
+    let gait = std::path::Path::new(out).with_extension("cpg.json");
+    std::fs::write(&gait, serde_json::to_string_pretty(&best.gait)?)?;
+    println!("saved {out} and its gait {}", gait.display());
+    Ok(())
+}
+
+/// Steps `envs` headless golems in lockstep and reports the throughput
+fn bench(envs: usize, steps: usize, env: game::env::EnvConfig) {
+    use controller::Controller;
+
+    let joints = env.joints();
+    let mut vec_env = game::env::VecEnv::new(envs, env);
+    let mut gaits: Vec<_> = (0..envs)
+        .map(|_| controller::ScriptedGait::new(joints))
+        .collect();
+    let mut observations = vec_env.reset();
+    let start = std::time::Instant::now();
+    let mut episodes = 0;
+    for _ in 0..steps {
+        let actions = gaits
+            .iter_mut()
+            .zip(&observations)
+            .map(|(gait, observation)| gait.act(observation))
+            .collect::<Vec<_>>();
+        let batch = vec_env.step(&actions);
+        for (i, gait) in gaits.iter_mut().enumerate() {
+            if batch.terminated[i] || batch.truncated[i] {
+                gait.reset();
+                episodes += 1;
+            }
+        }
+        observations = batch.observations;
+    }
+    let elapsed = start.elapsed().as_secs_f32();
+    println!(
+        "{} envs x {} steps in {:.2}s: {:.0} steps/s, {} episodes",
+        envs,
+        steps,
+        elapsed,
+        (envs * steps) as f32 / elapsed,
+        episodes
+    );
+}
+
+/// Drives a running `serve` instance through every request kind, as a stub of an external trainer
+fn client(addr: &str, steps: usize) -> Result<(), server::ServerError> {
+    use controller::Controller;
+    use server::{Request, Response};
+
+    let mut client = server::Client::connect(addr)?;
+    let Response::Spaces { action_size, .. } = client.request(&Request::Spaces)? else {
+        return Err(server::ServerError::Remote(
+            "unexpected spaces reply".into(),
+        ));
+    };
+    let mut gait = controller::ScriptedGait::new(action_size);
+    let Response::Reset { mut observation } = client.request(&Request::Reset { seed: Some(0) })?
+    else {
+        return Err(server::ServerError::Remote("unexpected reset reply".into()));
+    };
+    let mut total = 0.0;
+    for i in 0..steps {
+        let action = gait.act(&observation);
+        let Response::Step {
+            observation: next,
+            reward,
+            terminated,
+            truncated,
+        } = client.request(&Request::Step { action })?
+        else {
+            return Err(server::ServerError::Remote("unexpected step reply".into()));
+        };
+        observation = next;
+        total += reward;
+        if terminated || truncated {
+            println!("episode ended after {} steps, return {total}", i + 1);
+            total = 0.0;
+            gait.reset();
+            if let Response::Reset { observation: first } =
+                client.request(&Request::Reset { seed: None })?
+            {
+                observation = first;
+            }
+        }
+    }
+    client.request(&Request::Close)?;
+    Ok(())
+}
+
+/// Dataset writer to `out`, JSONL when it ends in `.jsonl` and a columnar directory otherwise
+fn create_writer(out: &str) -> std::io::Result<Box<dyn dataset::TrajectoryWriter + Send + Sync>> {
+    Ok(if out.ends_with(".jsonl") {
+        Box::new(dataset::JsonlWriter::create(out)?)
+    } else {
+        Box::new(dataset::ColumnarWriter::create(out)?)
+    })
+}
+
+/// Records scripted gait episodes with [`create_writer`]
+fn record(out: &str, episodes: usize, seed: u64, env: game::env::EnvConfig) -> std::io::Result<()> {
+    let mut writer = create_writer(out)?;
+    let mut env = game::env::GolemEnv::new(env);
+    let mut gait = controller::ScriptedGait::new(env.action_size());
+    dataset::record_rollouts(&mut env, &mut gait, writer.as_mut(), episodes, seed)?;
+    println!("recorded {episodes} episodes to {out}");
+    Ok(())
+}
+
+/// Clones the demonstrations of `dataset` into a policy saved to `out`, then evaluates it
+fn train_bc(
+    dataset: &str,
+    out: &str,
+    epochs: usize,
+    seed: u64,
+    env: game::env::EnvConfig,
+) -> std::io::Result<()> {
+    let demonstrations = dataset::Demonstrations::read(dataset)?;
+    if let Some(observation) = demonstrations.observations.first()
+        && observation.len() != env.observation_size()
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{dataset} holds observations of {} values, the environment has {}",
+                observation.len(),
+                env.observation_size()
+            ),
+        ));
+    }
+    println!(
+        "cloning {} demonstrated steps from {dataset}",
+        demonstrations.observations.len()
+    );
+    let config = bc::BcConfig {
+        epochs,
+        seed,
+        ..default()
+    };
+    let mlp = bc::train(&config, &demonstrations, |epoch, loss| {
+        println!("epoch {epoch}: loss {loss:.5}");
+    })?;
+    mlp.save(out)?;
+
+    let mut env = game::env::GolemEnv::new(env);
+    let mut controller = controller::MlpController {
+        mlp,
+        name: out.to_string(),
+    };
+    let report = eval::evaluate(&mut env, &mut controller, 5, seed);
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
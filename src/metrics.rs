@@ -0,0 +1,133 @@
+//! Scalar training metrics written to a run directory, readable offline.
+//!
+//! - `metrics.csv`: one `step,wall_time,tag,value` row per scalar, appended to by resumed runs.
+//! - `events.out.tfevents.<time>.<host>`: TensorBoard event file, a TFRecord stream of
+//!   `Event` protobufs encoded by hand, so `tensorboard --logdir <run>` reads it as is.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct MetricsLogger {
+    csv: BufWriter<File>,
+    events: BufWriter<File>,
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64())
+}
+
+impl MetricsLogger {
+    pub fn create(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let csv = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("metrics.csv"))?;
+        let empty = csv.metadata()?.len() == 0;
+        let mut csv = BufWriter::new(csv);
+        if empty {
+            writeln!(csv, "step,wall_time,tag,value")?;
+        }
+
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".into());
+        let name = format!("events.out.tfevents.{}.{host}", wall_time() as u64);
+        let mut events = BufWriter::new(File::create(dir.join(name))?);
+        // every event file starts with its version
+        let mut event = Vec::new();
+        encode_double(&mut event, 1, wall_time());
+        encode_bytes(&mut event, 3, b"brain.Event:2");
+        write_record(&mut events, &event)?;
+
+        Ok(Self { csv, events })
+    }
+
+    pub fn scalar(&mut self, tag: &str, step: u64, value: f32) -> io::Result<()> {
+        let time = wall_time();
+        writeln!(self.csv, "{step},{time:.3},{tag},{value}")?;
+
+        let mut summary_value = Vec::new();
+        encode_bytes(&mut summary_value, 1, tag.as_bytes());
+        encode_key(&mut summary_value, 2, 5);
+        summary_value.extend(value.to_le_bytes());
+        let mut summary = Vec::new();
+        encode_bytes(&mut summary, 1, &summary_value);
+        let mut event = Vec::new();
+        encode_double(&mut event, 1, time);
+        encode_key(&mut event, 2, 0);
+        encode_varint(&mut event, step);
+        encode_bytes(&mut event, 5, &summary);
+        write_record(&mut self.events, &event)
+    }
+
+    /// Makes the metrics logged so far visible to readers
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.csv.flush()?;
+        self.events.flush()
+    }
+}
+
+fn encode_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Protobuf field key, wire types being 0 varint, 1 64-bit, 2 length-delimited, 5 32-bit
+fn encode_key(out: &mut Vec<u8>, field: u64, wire_type: u64) {
+    encode_varint(out, field << 3 | wire_type);
+}
+
+fn encode_double(out: &mut Vec<u8>, field: u64, value: f64) {
+    encode_key(out, field, 1);
+    out.extend(value.to_le_bytes());
+}
+
+fn encode_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_key(out, field, 2);
+    encode_varint(out, bytes.len() as u64);
+    out.extend(bytes);
+}
+
+/// TFRecord framing: length, masked CRC of the length, data, masked CRC of the data
+fn write_record(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let length = (data.len() as u64).to_le_bytes();
+    out.write_all(&length)?;
+    out.write_all(&masked_crc(&length).to_le_bytes())?;
+    out.write_all(data)?;
+    out.write_all(&masked_crc(data).to_le_bytes())
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+/// CRC-32C (Castagnoli), bitwise as the records are small
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0x82f6_3b78 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_matches_the_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+}
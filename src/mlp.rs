@@ -0,0 +1,445 @@
+//! Small dense neural networks mapping golem observations to actions.
+//!
+//! Weight files are little-endian binaries:
+//!
+//! ```text
+//! magic        4 bytes  "GMLP"
+//! version      u32      1 or 2, files are written as version 2
+//! layers       u32      number of dense layers
+//! per layer:
+//!   inputs     u32
+//!   outputs    u32
+//!   activation u8       0 identity, 1 tanh, 2 relu
+//!   weights    f32 x outputs x inputs, row-major (one row per output)
+//!   biases     f32 x outputs
+//! normalized   u8       0 raw inputs, 1 followed by the input normalization (version 2)
+//!   mean       f32 x inputs of the first layer
+//!   std        f32 x inputs of the first layer
+//!   clip       f32
+//! ```
+//!
+//! Each layer's `inputs` must equal the previous layer's `outputs`. Version 1 files end
+//! after the last layer and feed observations to the network as is.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::normalize::Normalization;
+use crate::rng::Rng;
+
+const MAGIC: &[u8; 4] = b"GMLP";
+const VERSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activation {
+    Identity,
+    Tanh,
+    Relu,
+}
+
+impl Activation {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Activation::Identity => x,
+            Activation::Tanh => x.tanh(),
+            Activation::Relu => x.max(0.0),
+        }
+    }
+
+    /// Derivative expressed from the activated value `y`
+    fn derivative(self, y: f32) -> f32 {
+        match self {
+            Activation::Identity => 1.0,
+            Activation::Tanh => 1.0 - y * y,
+            Activation::Relu => (y > 0.0) as u8 as f32,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Activation::Identity => 0,
+            Activation::Tanh => 1,
+            Activation::Relu => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Activation::Identity),
+            1 => Ok(Activation::Tanh),
+            2 => Ok(Activation::Relu),
+            _ => Err(invalid(format!("unknown activation {byte}"))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Dense {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub activation: Activation,
+    /// `outputs x inputs`, row-major
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+impl Dense {
+    fn forward(&self, input: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        output.extend(
+            self.weights
+                .chunks_exact(self.inputs)
+                .zip(&self.biases)
+                .map(|(row, bias)| {
+                    let sum = row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>() + bias;
+                    self.activation.apply(sum)
+                }),
+        );
+    }
+}
+
+/// Multi-layer perceptron
+#[derive(Clone, Debug)]
+pub struct Mlp {
+    pub layers: Vec<Dense>,
+    /// Applied to inputs before the first layer, not part of [`Self::parameters`]
+    pub input: Option<Normalization>,
+}
+
+impl Mlp {
+    /// Network with layer widths `sizes`, `hidden` activations and a linear output layer,
+    /// initialized with scaled uniform weights and zero biases
+    pub fn random(sizes: &[usize], hidden: Activation, rng: &mut Rng) -> Self {
+        let layers = sizes
+            .windows(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let (inputs, outputs) = (pair[0], pair[1]);
+                let scale = (1.0 / inputs as f32).sqrt();
+                Dense {
+                    inputs,
+                    outputs,
+                    activation: if i + 2 == sizes.len() {
+                        Activation::Identity
+                    } else {
+                        hidden
+                    },
+                    weights: (0..inputs * outputs)
+                        .map(|_| rng.range(-scale, scale))
+                        .collect(),
+                    biases: vec![0.0; outputs],
+                }
+            })
+            .collect();
+        Self {
+            layers,
+            input: None,
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.inputs)
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.layers.last().map_or(0, |layer| layer.outputs)
+    }
+
+    /// Every weight and bias, layer by layer, in file order
+    pub fn parameters(&self) -> Vec<f32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.weights.iter().chain(&layer.biases))
+            .copied()
+            .collect()
+    }
+
+    /// Inverse of [`Self::parameters`]
+    pub fn set_parameters(&mut self, parameters: &[f32]) {
+        let mut values = parameters.iter();
+        for layer in &mut self.layers {
+            for value in layer.weights.iter_mut().chain(&mut layer.biases) {
+                *value = *values
+                    .next()
+                    .expect("parameter count differs from the network");
+            }
+        }
+    }
+
+    fn normalize(&self, input: &[f32]) -> Vec<f32> {
+        match &self.input {
+            Some(normalization) => normalization.apply(input),
+            None => input.to_vec(),
+        }
+    }
+
+    /// Output of every layer for `input`, the normalized input first, as needed by
+    /// [`Self::backward`]
+    pub fn forward_trace(&self, input: &[f32]) -> Vec<Vec<f32>> {
+        let mut trace = vec![self.normalize(input)];
+        for layer in &self.layers {
+            let mut output = Vec::new();
+            layer.forward(
+                trace.last().expect("trace starts with the input"),
+                &mut output,
+            );
+            trace.push(output);
+        }
+        trace
+    }
+
+    /// Backpropagates `grad_output`, the loss gradient at the network output, through `trace`
+    /// and accumulates the parameter gradients into `grads`, laid out like [`Self::parameters`]
+    pub fn backward(&self, trace: &[Vec<f32>], grad_output: &[f32], grads: &mut [f32]) {
+        let mut offset = grads.len();
+        let mut grad = grad_output.to_vec();
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let (input, output) = (&trace[i], &trace[i + 1]);
+            offset -= layer.weights.len() + layer.biases.len();
+            let (weight_grads, bias_grads) = grads
+                [offset..offset + layer.weights.len() + layer.biases.len()]
+                .split_at_mut(layer.weights.len());
+            let delta: Vec<f32> = grad
+                .iter()
+                .zip(output)
+                .map(|(g, &y)| g * layer.activation.derivative(y))
+                .collect();
+            let mut grad_input = vec![0.0; layer.inputs];
+            for (o, &d) in delta.iter().enumerate() {
+                bias_grads[o] += d;
+                let row = o * layer.inputs;
+                for (j, &x) in input.iter().enumerate() {
+                    weight_grads[row + j] += d * x;
+                    grad_input[j] += layer.weights[row + j] * d;
+                }
+            }
+            grad = grad_input;
+        }
+    }
+
+    pub fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let mut current = self.normalize(input);
+        let mut next = Vec::new();
+        for layer in &self.layers {
+            layer.forward(&current, &mut next);
+            std::mem::swap(&mut current, &mut next);
+        }
+        current
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()
+    }
+
+    fn read(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a policy weight file".into()));
+        }
+        let version = read_u32(input)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid(format!(
+                "unsupported weight file version {version}"
+            )));
+        }
+        let count = read_u32(input)?;
+        let mut layers: Vec<Dense> = Vec::new();
+        for _ in 0..count {
+            let inputs = read_u32(input)? as usize;
+            let outputs = read_u32(input)? as usize;
+            if inputs == 0 || outputs == 0 {
+                return Err(invalid(format!("empty {inputs}x{outputs} layer")));
+            }
+            let Some(weights) = inputs.checked_mul(outputs) else {
+                return Err(invalid(format!("oversized {inputs}x{outputs} layer")));
+            };
+            if let Some(previous) = layers.last()
+                && previous.outputs != inputs
+            {
+                return Err(invalid(format!(
+                    "layer expects {inputs} inputs but follows a layer of {} outputs",
+                    previous.outputs
+                )));
+            }
+            let mut activation = [0];
+            input.read_exact(&mut activation)?;
+            layers.push(Dense {
+                inputs,
+                outputs,
+                activation: Activation::from_byte(activation[0])?,
+                weights: read_f32s(input, weights)?,
+                biases: read_f32s(input, outputs)?,
+            });
+        }
+        let mut normalized = [0];
+        if version >= 2 {
+            input.read_exact(&mut normalized)?;
+        }
+        let normalization = match normalized[0] {
+            0 => None,
+            _ => {
+                let size = layers.first().map_or(0, |layer| layer.inputs);
+                Some(Normalization {
+                    mean: read_f32s(input, size)?,
+                    std: read_f32s(input, size)?,
+                    clip: read_f32s(input, 1)?[0],
+                })
+            }
+        };
+        Ok(Self {
+            layers,
+            input: normalization,
+        })
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(self.layers.len() as u32).to_le_bytes())?;
+        for layer in &self.layers {
+            out.write_all(&(layer.inputs as u32).to_le_bytes())?;
+            out.write_all(&(layer.outputs as u32).to_le_bytes())?;
+            out.write_all(&[layer.activation.to_byte()])?;
+            for value in layer.weights.iter().chain(&layer.biases) {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        match &self.input {
+            Some(normalization) => {
+                out.write_all(&[1])?;
+                let values = normalization.mean.iter().chain(&normalization.std);
+                for value in values.chain([&normalization.clip]) {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+            None => out.write_all(&[0])?,
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Reads `count` floats, growing the buffer with what the input actually holds so that a
+/// corrupted count fails on the missing data instead of allocating it upfront
+fn read_f32s(input: &mut impl Read, count: usize) -> io::Result<Vec<f32>> {
+    let Some(size) = count.checked_mul(4) else {
+        return Err(invalid(format!("oversized block of {count} values")));
+    };
+    let mut bytes = Vec::new();
+    input.take(size as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "expected {count} values, the file ends after {}",
+                bytes.len() / 4
+            ),
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network() -> Mlp {
+        let mut mlp = Mlp::random(&[3, 5, 4, 2], Activation::Tanh, &mut Rng::new(0));
+        mlp.input = Some(Normalization {
+            mean: vec![0.1, -0.2, 0.3],
+            std: vec![0.5, 2.0, 1.0],
+            clip: 5.0,
+        });
+        mlp
+    }
+
+    #[test]
+    fn backward_matches_finite_differences() {
+        let mut mlp = network();
+        let input = [0.3, -0.7, 0.5];
+        // the loss is the output dotted with `weights`, its gradient at the output
+        let weights = [0.8, -1.3];
+        let loss = |mlp: &Mlp| -> f32 {
+            let output = mlp.forward(&input);
+            output.iter().zip(weights).map(|(y, w)| y * w).sum()
+        };
+        let mut grads = vec![0.0; mlp.parameters().len()];
+        mlp.backward(&mlp.forward_trace(&input), &weights, &mut grads);
+
+        let parameters = mlp.parameters();
+        let eps = 1e-2;
+        for (i, analytic) in grads.into_iter().enumerate() {
+            let mut shifted = parameters.clone();
+            shifted[i] += eps;
+            mlp.set_parameters(&shifted);
+            let up = loss(&mlp);
+            shifted[i] -= 2.0 * eps;
+            mlp.set_parameters(&shifted);
+            let numeric = (up - loss(&mlp)) / (2.0 * eps);
+            assert!(
+                (analytic - numeric).abs() < 1e-3,
+                "parameter {i}: {analytic} against {numeric}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() -> io::Result<()> {
+        let mut mlp = network();
+        let mut bytes = Vec::new();
+        mlp.write(&mut bytes)?;
+        let read = Mlp::read(&mut bytes.as_slice())?;
+        assert_eq!(read.parameters(), mlp.parameters());
+        assert_eq!(read.input, mlp.input);
+        for (read, layer) in read.layers.iter().zip(&mlp.layers) {
+            assert_eq!(
+                (read.inputs, read.outputs, read.activation),
+                (layer.inputs, layer.outputs, layer.activation)
+            );
+        }
+
+        // version 1 files end after the last layer
+        mlp.input = None;
+        bytes.clear();
+        mlp.write(&mut bytes)?;
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        bytes.pop();
+        let read = Mlp::read(&mut bytes.as_slice())?;
+        assert_eq!(read.parameters(), mlp.parameters());
+        assert_eq!(read.input, None);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_corrupted_sizes() {
+        let mut bytes = Vec::new();
+        network().write(&mut bytes).unwrap();
+        // sizes of the first layer, right after the magic, version and layer count
+        for (inputs, outputs) in [(0, 5), (3, 0), (1 << 20, 1 << 20), (u32::MAX, u32::MAX)] {
+            let mut corrupted = bytes.clone();
+            corrupted[12..16].copy_from_slice(&inputs.to_le_bytes());
+            corrupted[16..20].copy_from_slice(&outputs.to_le_bytes());
+            assert!(Mlp::read(&mut corrupted.as_slice()).is_err());
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Morphology evolution, searching golem bodies together with the gaits driving them.
+//!
+//! An outer genetic loop keeps a population of [`GolemBlueprint`]s. Every new body gets a
+//! fresh CPG tuned by a short ES run, the inner loop, and its fitness is the mean return of
+//! the tuned gait over seeded episodes. The `elites` fittest bodies survive each generation,
+//! measured again on its seeds alongside the newcomers, and the rest of the population is
+//! refilled with mutated copies of them: limbs mirrored or removed, and their offsets, axes,
+//! travel, strokes and sizes perturbed.
+
+use bevy::prelude::*;
+
+use crate::controller::CpgController;
+use crate::es::{self, EsConfig, EsTrainer};
+use crate::game::blueprint::{GolemBlueprint, LimbBlueprint};
+use crate::game::env::{EnvConfig, GolemEnv};
+use crate::rng::Rng;
+
+/// Bounds on the half extents of a segment
+const SIZE_RANGE: [f32; 2] = [0.1, 1.0];
+
+/// Bounds on the joint travel
+const LIMIT_RANGE: [f32; 2] = [-2.0, 2.0];
+
+/// Shortest travel a joint keeps, below it the limb could barely move
+const MIN_TRAVEL: f32 = 0.1;
+
+#[derive(Clone, Debug)]
+pub struct MorphologyConfig {
+    pub population: usize,
+    /// Fittest bodies carried over to the next generation
+    pub elites: usize,
+    /// ES generations tuning the gait of every new body
+    pub inner_generations: u64,
+    /// Episodes the fitness of a tuned body is averaged over
+    pub episodes: usize,
+    /// Standard deviation of the perturbations, in world units
+    pub mutation: f32,
+    /// Probability a mutation adds or removes a limb
+    pub structural: f32,
+    pub max_limbs: usize,
+    /// Settings of the inner loop, its `env` giving the task every body is evaluated on
+    pub es: EsConfig,
+}
+
+impl Default for MorphologyConfig {
+    fn default() -> Self {
+        Self {
+            population: 8,
+            elites: 2,
+            inner_generations: 5,
+            episodes: 2,
+            mutation: 0.2,
+            structural: 0.2,
+            max_limbs: 6,
+            es: EsConfig {
+                population: 8,
+                ..default()
+            },
+        }
+    }
+}
+
+/// A body with its tuned gait
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub blueprint: GolemBlueprint,
+    pub gait: CpgController,
+    pub fitness: f32,
+}
+
+/// Fitness of the population after one generation
+#[derive(Clone, Debug)]
+pub struct Generation {
+    pub index: u64,
+    pub mean_fitness: f32,
+    /// Fittest body so far
+    pub best: Candidate,
+}
+
+pub struct MorphologySearch {
+    pub config: MorphologyConfig,
+    /// Bodies of the current generation, fittest first once evaluated
+    pub population: Vec<Candidate>,
+    pub rng: Rng,
+    pub generation: u64,
+}
+
+/// `value` moved by a gaussian draw of standard deviation `scale`
+fn perturb(value: f32, scale: f32, rng: &mut Rng) -> f32 {
+    value + scale * rng.normal()
+}
+
+fn perturb_vec(value: Vec3, scale: f32, rng: &mut Rng) -> Vec3 {
+    Vec3::new(
+        perturb(value.x, scale, rng),
+        perturb(value.y, scale, rng),
+        perturb(value.z, scale, rng),
+    )
+}
+
+fn perturb_size(size: Vec3, scale: f32, rng: &mut Rng) -> Vec3 {
+    perturb_vec(size, scale, rng).clamp(Vec3::splat(SIZE_RANGE[0]), Vec3::splat(SIZE_RANGE[1]))
+}
+
+/// Perturbs every parameter of `limb`, keeping its travel at least [`MIN_TRAVEL`] long and
+/// its stroke within it, the stroke ends staying [`MIN_TRAVEL`] apart as well
+fn mutate_limb(limb: &mut LimbBlueprint, scale: f32, rng: &mut Rng) {
+    limb.offset = perturb_vec(limb.offset, scale, rng);
+    limb.tip = perturb_vec(limb.tip, scale, rng);
+    limb.axis = perturb_vec(limb.axis, scale, rng).normalize_or(Vec3::Z);
+    let [low, high] = limb.limits.map(|limit| perturb(limit, scale, rng));
+    let low = low
+        .min(high)
+        .clamp(LIMIT_RANGE[0], LIMIT_RANGE[1] - MIN_TRAVEL);
+    let high = high.max(low + MIN_TRAVEL).min(LIMIT_RANGE[1]);
+    limb.limits = [low, high];
+    let forward = limb.stroke[1] >= limb.stroke[0];
+    let [from, to] = limb
+        .stroke
+        .map(|target| perturb(target, scale, rng).clamp(low, high));
+    limb.stroke = if (to - from).abs() >= MIN_TRAVEL {
+        [from, to]
+    } else {
+        // spread around their middle, actions being scaled by the distance between them
+        let half = MIN_TRAVEL / 2.0;
+        let middle = ((from + to) / 2.0).max(low + half).min(high - half);
+        let half = if to > from || (to == from && forward) {
+            half
+        } else {
+            -half
+        };
+        [middle - half, middle + half]
+    };
+    limb.size = perturb_size(limb.size, scale, rng);
+}
+
+/// Mutated copy of `blueprint`
+///
+/// A structural mutation removes a limb or adds the mirror image of one across the
+/// sagittal plane, never leaving the golem without limbs nor above `max_limbs`.
+pub fn mutate(
+    blueprint: &GolemBlueprint,
+    config: &MorphologyConfig,
+    rng: &mut Rng,
+) -> GolemBlueprint {
+    let mut child = blueprint.clone();
+    let limbs = child.limbs.len();
+    if limbs > 0 && rng.next_f32() < config.structural {
+        let i = (rng.next_u64() % limbs as u64) as usize;
+        let grow = rng.next_f32() < 0.5;
+        if (grow || limbs == 1) && limbs < config.max_limbs {
+            let mirror = Vec3::new(-1.0, 1.0, 1.0);
+            let limb = &child.limbs[i];
+            child.limbs.push(LimbBlueprint {
+                offset: limb.offset * mirror,
+                tip: limb.tip * mirror,
+                axis: limb.axis * mirror,
+                ..limb.clone()
+            });
+        } else if limbs > 1 {
+            child.limbs.remove(i);
+        }
+    }
+    child.head_size = perturb_size(child.head_size, config.mutation, rng);
+    for limb in &mut child.limbs {
+        mutate_limb(limb, config.mutation, rng);
+    }
+    child
+}
+
+impl MorphologySearch {
+    /// Search starting from the body of `config.es.env` and mutations of it
+    pub fn new(config: MorphologyConfig) -> Self {
+        let mut rng = Rng::new(config.es.env.seed);
+        let origin = config.es.env.blueprint.clone();
+        let blueprints: Vec<_> = std::iter::once(origin.clone())
+            .chain((1..config.population.max(1)).map(|_| mutate(&origin, &config, &mut rng)))
+            .collect();
+        let mut search = Self {
+            population: Vec::new(),
+            rng,
+            generation: 0,
+            config,
+        };
+        search.population = search.evaluate(blueprints);
+        search
+    }
+
+    fn env(&self, blueprint: &GolemBlueprint, seed: u64) -> EnvConfig {
+        EnvConfig {
+            blueprint: blueprint.clone(),
+            seed,
+            ..self.config.es.env.clone()
+        }
+    }
+
+    /// Mean return of `gait` driving `blueprint` over the episodes seeded from `seed`
+    fn fitness(&self, blueprint: &GolemBlueprint, gait: &mut CpgController, seed: u64) -> f32 {
+        let mut env = GolemEnv::new(self.env(blueprint, seed));
+        let episodes = self.config.episodes.max(1);
+        let total: f32 = (0..episodes as u64)
+            .map(|e| es::episode_return(&mut env, gait, seed + e).0)
+            .sum();
+        total / episodes as f32
+    }
+
+    /// Tunes a gait for `blueprint` then measures its mean return
+    fn tune(&self, blueprint: GolemBlueprint, seed: u64) -> Candidate {
+        let env = self.env(&blueprint, seed);
+        let gait = CpgController::new(env.joints(), env.dt);
+        let mut trainer = EsTrainer::new(
+            EsConfig {
+                env: env.clone(),
+                ..self.config.es.clone()
+            },
+            gait,
+        );
+        while trainer.generation < self.config.inner_generations {
+            trainer.step();
+        }
+        let mut gait = trainer.policy;
+        let fitness = self.fitness(&blueprint, &mut gait, seed);
+        Candidate {
+            blueprint,
+            gait,
+            fitness,
+        }
+    }
+
+    /// First episode seed of the current generation, shared by all its candidates
+    fn seed(&self) -> u64 {
+        self.config
+            .es
+            .env
+            .seed
+            .wrapping_add(self.generation * self.config.episodes as u64)
+    }
+
+    /// Candidates of `blueprints`, fittest first, all of a generation sharing the same seeds
+    fn evaluate(&self, blueprints: Vec<GolemBlueprint>) -> Vec<Candidate> {
+        let seed = self.seed();
+        let mut candidates: Vec<_> = blueprints
+            .into_iter()
+            .map(|blueprint| self.tune(blueprint, seed))
+            .collect();
+        candidates.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+        candidates
+    }
+
+    /// Runs one generation: mutates the elites into the rest of the population, evaluates
+    /// the offspring and measures the elites again on the same seeds, keeping their gaits
+    pub fn step(&mut self) -> Generation {
+        let elites = self.config.elites.clamp(1, self.population.len().max(1));
+        self.population.truncate(elites);
+        let offspring: Vec<_> = (elites..self.config.population.max(elites))
+            .map(|i| {
+                let parent = &self.population[i % elites].blueprint;
+                mutate(parent, &self.config, &mut self.rng)
+            })
+            .collect();
+        self.generation += 1;
+        let seed = self.seed();
+        let mut elites = std::mem::take(&mut self.population);
+        for elite in &mut elites {
+            elite.fitness = self.fitness(&elite.blueprint, &mut elite.gait, seed);
+        }
+        self.population = elites;
+        let offspring = self.evaluate(offspring);
+        self.population.extend(offspring);
+        self.population
+            .sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+
+        let fitness: f32 = self.population.iter().map(|c| c.fitness).sum();
+        Generation {
+            index: self.generation,
+            mean_fitness: fitness / self.population.len().max(1) as f32,
+            best: self.population[0].clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mutated_strokes_stay_within_travel_and_apart() {
+        let mut rng = Rng::new(0);
+        let mut limb = GolemBlueprint::default().limbs[0].clone();
+        limb.stroke = [0.5, 0.5];
+        for _ in 0..1000 {
+            mutate_limb(&mut limb, 0.5, &mut rng);
+            let [low, high] = limb.limits;
+            let [from, to] = limb.stroke;
+            assert!(high - low >= MIN_TRAVEL - 1e-6, "{:?}", limb.limits);
+            assert!((to - from).abs() >= MIN_TRAVEL - 1e-6, "{:?}", limb.stroke);
+            for end in limb.stroke {
+                assert!(low - 1e-6 <= end && end <= high + 1e-6, "{limb:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+/// Weight of the prior of [`RunningStats`], a mean of 0 and a variance of 1, against the
+/// samples
+const PRIOR_COUNT: f64 = 1e-4;
+
+/// Running mean and variance of a stream of vectors (Welford)
+///
+/// The statistics start from a unit variance prior worth [`PRIOR_COUNT`] samples, which
+/// keeps the variance of the first samples away from 0.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunningStats {
+    pub count: f64,
+    pub mean: Vec<f64>,
+    /// Population variance of the samples seen so far
+    pub var: Vec<f64>,
+}
+
+impl RunningStats {
+    pub fn new(size: usize) -> Self {
+        Self {
+            count: PRIOR_COUNT,
+            mean: vec![0.0; size],
+            var: vec![1.0; size],
+        }
+    }
+
+    /// Standard deviation of every component, floored to keep divisions finite
+    pub fn std(&self) -> Vec<f32> {
+        self.var
+            .iter()
+            .map(|var| (var.sqrt() as f32).max(1e-4))
+            .collect()
+    }
+
+    /// Frozen normalization standardizing samples like the ones seen so far
+    pub fn normalization(&self, clip: f32) -> Normalization {
+        Normalization {
+            mean: self.mean.iter().map(|&mean| mean as f32).collect(),
+            std: self.std(),
+            clip,
+        }
+    }
+
+    pub fn update(&mut self, sample: &[f32]) {
+        self.count += 1.0;
+        for ((mean, var), &x) in self.mean.iter_mut().zip(&mut self.var).zip(sample) {
+            let delta = x as f64 - *mean;
+            *mean += delta / self.count;
+            let m2 = *var * (self.count - 1.0) + delta * (x as f64 - *mean);
+            *var = m2 / self.count;
+        }
+    }
+}
+
+/// Standardization applied to network inputs, `(x - mean) / std` clipped to `[-clip, clip]`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Normalization {
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+    pub clip: f32,
+}
+
+impl Normalization {
+    pub fn apply(&self, sample: &[f32]) -> Vec<f32> {
+        sample
+            .iter()
+            .zip(&self.mean)
+            .zip(&self.std)
+            .map(|((x, mean), std)| ((x - mean) / std).clamp(-self.clip, self.clip))
+            .collect()
+    }
+}
+
+/// Scales rewards by the standard deviation of the discounted return of each environment
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReturnNormalizer {
+    pub gamma: f32,
+    pub stats: RunningStats,
+    /// Discounted return of the running episode of each environment
+    returns: Vec<f32>,
+}
+
+impl ReturnNormalizer {
+    pub fn new(envs: usize, gamma: f32) -> Self {
+        Self {
+            gamma,
+            stats: RunningStats::new(1),
+            returns: vec![0.0; envs],
+        }
+    }
+
+    /// Scaled `reward` of environment `env`, whose episode ends when `done`, clipped to
+    /// `[-clip, clip]`
+    pub fn normalize(&mut self, env: usize, reward: f32, done: bool, clip: f32) -> f32 {
+        self.returns[env] = self.gamma * self.returns[env] + reward;
+        self.stats.update(&[self.returns[env]]);
+        if done {
+            self.returns[env] = 0.0;
+        }
+        (reward / self.stats.std()[0]).clamp(-clip, clip)
+    }
+
+    /// Forgets the returns of the running episodes, once their environments were reset
+    pub fn restart(&mut self) {
+        self.returns.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-3, "{actual:?} against {expected:?}");
+        }
+    }
+
+    #[test]
+    fn running_stats_follow_the_sample_moments() {
+        let mut stats = RunningStats::new(2);
+        for sample in [[1.0, -2.0], [3.0, 0.0], [5.0, 4.0], [7.0, 2.0]] {
+            stats.update(&sample);
+        }
+        assert_close(&stats.mean, &[4.0, 1.0]);
+        assert_close(&stats.var, &[5.0, 5.0]);
+
+        let normalization = stats.normalization(1.0);
+        let normalized: Vec<f64> = normalization
+            .apply(&[4.0 + 5f32.sqrt() / 2.0, 100.0])
+            .into_iter()
+            .map(f64::from)
+            .collect();
+        assert_close(&normalized, &[0.5, 1.0]);
+    }
+
+    #[test]
+    fn first_sample_keeps_a_positive_variance() {
+        let mut stats = RunningStats::new(1);
+        stats.update(&[3.0]);
+        assert_close(&stats.mean, &[3.0]);
+        assert!(stats.var[0] > 0.0 && stats.std()[0] > 1e-4);
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// Adam optimizer over a flat parameter vector
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Adam {
+    pub learning_rate: f32,
+    pub beta1: f32,
+    pub beta2: f32,
+    pub epsilon: f32,
+    /// Updates applied so far
+    pub t: u64,
+    m: Vec<f32>,
+    v: Vec<f32>,
+}
+
+impl Adam {
+    pub fn new(size: usize, learning_rate: f32) -> Self {
+        Self {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            t: 0,
+            m: vec![0.0; size],
+            v: vec![0.0; size],
+        }
+    }
+
+    /// Moves `params` against `grad`, i.e. minimizes
+    pub fn step(&mut self, params: &mut [f32], grad: &[f32]) {
+        self.t += 1;
+        let correction1 = 1.0 - self.beta1.powi(self.t as i32);
+        let correction2 = 1.0 - self.beta2.powi(self.t as i32);
+        for (i, (param, &g)) in params.iter_mut().zip(grad).enumerate() {
+            self.m[i] = self.beta1 * self.m[i] + (1.0 - self.beta1) * g;
+            self.v[i] = self.beta2 * self.v[i] + (1.0 - self.beta2) * g * g;
+            let m = self.m[i] / correction1;
+            let v = self.v[i] / correction2;
+            *param -= self.learning_rate * m / (v.sqrt() + self.epsilon);
+        }
+    }
+}
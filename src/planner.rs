@@ -0,0 +1,162 @@
+//! Cross-entropy method planner, a model predictive controller using the environment itself
+//! as its model.
+//!
+//! Before every step the planner snapshots the environment, rolls out `population` joint
+//! action sequences `horizon` steps ahead from that frame, refits the gaussian it samples
+//! them from to the `elites` best returns for a few `iterations`, rewinds the environment
+//! and executes the first action of the resulting plan. The rest of the plan, shifted by
+//! one step, seeds the search of the next step.
+
+use crate::controller::Controller;
+use crate::game::env::GolemEnv;
+use crate::rng::Rng;
+
+#[derive(Clone, Debug)]
+pub struct CemConfig {
+    /// Steps simulated ahead by each candidate sequence
+    pub horizon: usize,
+    /// Candidate sequences rolled out per iteration
+    pub population: usize,
+    /// Best candidates the sampling distribution is refitted to
+    pub elites: usize,
+    pub iterations: usize,
+    /// Standard deviation the search starts from at every step, as a fraction of the action range
+    pub initial_std: f32,
+    /// Floor on the standard deviation, keeping some exploration once the elites agree
+    pub min_std: f32,
+    pub seed: u64,
+}
+
+impl Default for CemConfig {
+    fn default() -> Self {
+        Self {
+            horizon: 10,
+            population: 16,
+            elites: 4,
+            iterations: 2,
+            initial_std: 0.3,
+            min_std: 0.05,
+            seed: 0,
+        }
+    }
+}
+
+pub struct CemPlanner {
+    pub config: CemConfig,
+    joints: usize,
+    rng: Rng,
+    /// Mean action of each upcoming step
+    plan: Vec<Vec<f32>>,
+}
+
+impl CemPlanner {
+    pub fn new(config: CemConfig, joints: usize) -> Self {
+        Self {
+            rng: Rng::new(config.seed),
+            joints,
+            plan: Vec::new(),
+            config,
+        }
+    }
+
+    /// Searches for the best action sequence from the current frame of `env`, which is left
+    /// as it was, and returns its first action
+    pub fn plan(&mut self, env: &mut GolemEnv) -> Vec<f32> {
+        let high = env.action_space().high();
+        let CemConfig {
+            horizon,
+            population,
+            iterations,
+            ..
+        } = self.config;
+        self.plan.resize(horizon, vec![high / 2.0; self.joints]);
+        let mut std = vec![vec![self.config.initial_std * high; self.joints]; horizon];
+
+        let start = env.snapshot();
+        for _ in 0..iterations {
+            let mut candidates: Vec<(f32, Vec<Vec<f32>>)> = (0..population)
+                .map(|_| {
+                    let actions: Vec<Vec<f32>> = self
+                        .plan
+                        .iter()
+                        .zip(&std)
+                        .map(|(mean, std)| {
+                            mean.iter()
+                                .zip(std)
+                                .map(|(mean, std)| {
+                                    (mean + std * self.rng.normal()).clamp(0.0, high)
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    env.restore(&start);
+                    (rollout(env, &actions), actions)
+                })
+                .collect();
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let elites = &candidates[..self.config.elites.clamp(1, candidates.len())];
+
+            let n = elites.len() as f32;
+            for (t, (mean, std)) in self.plan.iter_mut().zip(&mut std).enumerate() {
+                for j in 0..self.joints {
+                    let m = elites.iter().map(|(_, a)| a[t][j]).sum::<f32>() / n;
+                    let var = elites
+                        .iter()
+                        .map(|(_, a)| (a[t][j] - m).powi(2))
+                        .sum::<f32>()
+                        / n;
+                    mean[j] = m;
+                    std[j] = var.sqrt().max(self.config.min_std * high);
+                }
+            }
+        }
+        env.restore(&start);
+
+        // the rest of the plan warm starts the next search
+        if self.plan.is_empty() {
+            vec![high / 2.0; self.joints]
+        } else {
+            self.plan.remove(0)
+        }
+    }
+}
+
+/// Return of `actions` played from the current frame, cut short when the episode ends
+fn rollout(env: &mut GolemEnv, actions: &[Vec<f32>]) -> f32 {
+    let mut total = 0.0;
+    for action in actions {
+        let step = env.step(action);
+        total += step.reward;
+        if step.done() {
+            break;
+        }
+    }
+    total
+}
+
+impl Controller for CemPlanner {
+    fn name(&self) -> String {
+        format!(
+            "cem-h{}-p{}x{}",
+            self.config.horizon, self.config.population, self.config.iterations
+        )
+    }
+
+    /// Plays the current plan open loop, planning needs the environment
+    fn act(&mut self, _observation: &[f32]) -> Vec<f32> {
+        if self.plan.is_empty() {
+            vec![0.5; self.joints]
+        } else {
+            self.plan.remove(0)
+        }
+    }
+
+    fn act_in(&mut self, env: &mut GolemEnv, _observation: &[f32]) -> Vec<f32> {
+        self.plan(env)
+    }
+
+    fn reset(&mut self) {
+        self.rng = Rng::new(self.config.seed);
+        self.plan.clear();
+    }
+}
@@ -0,0 +1,620 @@
+//! Proximal policy optimization on vectorized golem environments.
+//!
+//! The policy is a gaussian whose mean is an [`Mlp`] and whose log standard deviation is a
+//! learned vector independent of the observation; a second [`Mlp`] estimates state values.
+//! Advantages come from GAE, and both networks are updated with the clipped surrogate
+//! objective over shuffled minibatches, gradients being backpropagated by hand.
+//!
+//! Observations are standardized with their running statistics, frozen into both networks
+//! at the start of every iteration and saved with their weights, and rewards are scaled by
+//! the running standard deviation of the discounted return.
+//!
+//! Episodes cut by the time limit are bootstrapped from the value of their last
+//! observation, only terminal states being worth nothing past them.
+//!
+//! With a [`Curriculum`], every finished episode counts towards its success rate and the
+//! environments switch to the new difficulty at their next reset.
+
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint;
+use crate::curriculum::{Curriculum, CurriculumConfig};
+use crate::game::env::{EnvConfig, VecEnv};
+use crate::mlp::{Activation, Mlp};
+use crate::normalize::{ReturnNormalizer, RunningStats};
+use crate::optim::Adam;
+use crate::rng::Rng;
+
+const LOG_SQRT_TAU: f32 = 0.918_938_5;
+
+#[derive(Clone, Debug)]
+pub struct PpoConfig {
+    pub envs: usize,
+    /// Steps collected from every environment per iteration
+    pub steps: usize,
+    pub epochs: usize,
+    pub minibatch: usize,
+    pub gamma: f32,
+    /// GAE smoothing factor
+    pub lambda: f32,
+    pub clip: f32,
+    pub learning_rate: f32,
+    pub value_coef: f32,
+    pub entropy_coef: f32,
+    /// Largest global gradient norm, larger gradients are scaled down
+    pub max_grad_norm: f32,
+    pub hidden: usize,
+    pub initial_log_std: f32,
+    pub normalize_observations: bool,
+    /// Bound of standardized observations
+    pub observation_clip: f32,
+    pub normalize_rewards: bool,
+    /// Bound of scaled rewards, keeping rare large ones such as the fall penalty from
+    /// dominating an update once the return scale has settled on the shaping rewards
+    pub reward_clip: f32,
+    pub env: EnvConfig,
+    /// Difficulty schedule of `env`, trained as configured without one
+    pub curriculum: Option<CurriculumConfig>,
+}
+
+impl Default for PpoConfig {
+    fn default() -> Self {
+        Self {
+            envs: 8,
+            steps: 256,
+            epochs: 4,
+            minibatch: 256,
+            gamma: 0.99,
+            lambda: 0.95,
+            clip: 0.2,
+            learning_rate: 3e-4,
+            value_coef: 0.5,
+            entropy_coef: 0.0,
+            max_grad_norm: 0.5,
+            hidden: 64,
+            initial_log_std: -0.5,
+            normalize_observations: true,
+            observation_clip: 10.0,
+            normalize_rewards: true,
+            reward_clip: 10.0,
+            env: EnvConfig::default(),
+            curriculum: None,
+        }
+    }
+}
+
+/// Summary of one collect and update cycle
+#[derive(Clone, Debug, Default)]
+pub struct Iteration {
+    pub index: u64,
+    /// Environment steps collected since the start of training
+    pub total_steps: u64,
+    /// Episodes completed during the iteration and their mean return
+    pub episodes: usize,
+    pub mean_return: f32,
+    pub policy_loss: f32,
+    pub value_loss: f32,
+    pub entropy: f32,
+    /// Curriculum level at the end of the iteration
+    pub level: Option<f32>,
+}
+
+/// Loss gradients of a minibatch, laid out like the optimized parameters
+struct Gradients {
+    /// Policy network parameters followed by `log_std`
+    policy: Vec<f32>,
+    value: Vec<f32>,
+}
+
+/// Rollout storage, indexed `[step * envs + env]`
+#[derive(Default)]
+struct Batch {
+    observations: Vec<Vec<f32>>,
+    actions: Vec<Vec<f32>>,
+    log_probs: Vec<f32>,
+    values: Vec<f32>,
+    rewards: Vec<f32>,
+    terminated: Vec<bool>,
+    truncated: Vec<bool>,
+    /// Value of the last observation of the episodes truncated at a step, 0 elsewhere
+    bootstrap: Vec<f32>,
+    advantages: Vec<f32>,
+    returns: Vec<f32>,
+}
+
+/// Everything but the networks a resumed run needs, stored as the checkpoint `state.json`
+#[derive(Serialize, Deserialize)]
+struct PpoState {
+    iteration: u64,
+    total_steps: u64,
+    rng: Rng,
+    log_std: Vec<f32>,
+    policy_optimizer: Adam,
+    value_optimizer: Adam,
+    observation_stats: RunningStats,
+    return_normalizer: ReturnNormalizer,
+    #[serde(default)]
+    curriculum: Option<Curriculum>,
+}
+
+pub struct PpoTrainer {
+    pub config: PpoConfig,
+    /// Mean of the action distribution, the network the game plays back
+    pub policy: Mlp,
+    pub log_std: Vec<f32>,
+    pub value: Mlp,
+    pub policy_optimizer: Adam,
+    pub value_optimizer: Adam,
+    pub rng: Rng,
+    pub iteration: u64,
+    pub total_steps: u64,
+    /// Statistics of every observation collected so far
+    pub observation_stats: RunningStats,
+    pub return_normalizer: ReturnNormalizer,
+    pub curriculum: Option<Curriculum>,
+    envs: VecEnv,
+    observations: Vec<Vec<f32>>,
+    /// Return accumulated so far by the running episode of each environment
+    running_returns: Vec<f32>,
+}
+
+impl PpoTrainer {
+    pub fn new(config: PpoConfig) -> Self {
+        let mut rng = Rng::new(config.env.seed);
+        let (observation_size, action_size) =
+            (config.env.observation_size(), config.env.action_size());
+        let curriculum = config.curriculum.clone().map(Curriculum::new);
+        let env = match &curriculum {
+            Some(curriculum) => curriculum.apply(&config.env),
+            None => config.env.clone(),
+        };
+        let mut envs = VecEnv::new(config.envs, env);
+        let policy = Mlp::random(
+            &[observation_size, config.hidden, config.hidden, action_size],
+            Activation::Tanh,
+            &mut rng,
+        );
+        let value = Mlp::random(
+            &[observation_size, config.hidden, config.hidden, 1],
+            Activation::Tanh,
+            &mut rng,
+        );
+        let observations = envs.reset();
+        Self {
+            policy_optimizer: Adam::new(
+                policy.parameters().len() + action_size,
+                config.learning_rate,
+            ),
+            value_optimizer: Adam::new(value.parameters().len(), config.learning_rate),
+            log_std: vec![config.initial_log_std; action_size],
+            running_returns: vec![0.0; config.envs],
+            observation_stats: RunningStats::new(observation_size),
+            return_normalizer: ReturnNormalizer::new(config.envs, config.gamma),
+            iteration: 0,
+            total_steps: 0,
+            curriculum,
+            config,
+            policy,
+            value,
+            rng,
+            envs,
+            observations,
+        }
+    }
+
+    /// Restores a run saved by [`Self::save_checkpoint`] into `dir`
+    pub fn resume(config: PpoConfig, dir: &Path) -> io::Result<Self> {
+        let state: PpoState = checkpoint::load_state(dir)?;
+        let mut trainer = Self::new(config);
+        trainer.policy = checkpoint::load_mlp(dir, "policy", state.iteration)?;
+        trainer.value = checkpoint::load_mlp(dir, "value", state.iteration)?;
+        trainer.iteration = state.iteration;
+        trainer.total_steps = state.total_steps;
+        trainer.rng = state.rng;
+        trainer.log_std = state.log_std;
+        trainer.policy_optimizer = state.policy_optimizer;
+        trainer.value_optimizer = state.value_optimizer;
+        trainer.observation_stats = state.observation_stats;
+        trainer.return_normalizer = state.return_normalizer;
+        if let Some(config) = trainer.config.curriculum.clone() {
+            let curriculum = Curriculum::resume(config, state.curriculum);
+            trainer
+                .envs
+                .configure(&curriculum.apply(&trainer.config.env));
+            trainer.curriculum = Some(curriculum);
+        }
+        // the episodes the run was in are lost, the new ones are drawn from the saved random
+        // state rather than replaying those the run started with
+        let seed = trainer.rng.next_u64();
+        trainer.envs.reseed(seed);
+        trainer.observations = trainer.envs.reset();
+        trainer.return_normalizer.restart();
+        Ok(trainer)
+    }
+
+    pub fn save_checkpoint(&self, dir: &Path) -> io::Result<()> {
+        checkpoint::save_mlp(dir, "policy", self.iteration, &self.policy)?;
+        checkpoint::save_mlp(dir, "value", self.iteration, &self.value)?;
+        checkpoint::save_state(
+            dir,
+            &PpoState {
+                iteration: self.iteration,
+                total_steps: self.total_steps,
+                rng: self.rng.clone(),
+                log_std: self.log_std.clone(),
+                policy_optimizer: self.policy_optimizer.clone(),
+                value_optimizer: self.value_optimizer.clone(),
+                observation_stats: self.observation_stats.clone(),
+                return_normalizer: self.return_normalizer.clone(),
+                curriculum: self.curriculum.clone(),
+            },
+        )?;
+        checkpoint::prune_weights(dir, self.iteration)
+    }
+
+    fn log_prob(&self, mean: &[f32], action: &[f32]) -> f32 {
+        mean.iter()
+            .zip(action)
+            .zip(&self.log_std)
+            .map(|((mu, a), log_std)| {
+                let z = (a - mu) / log_std.exp();
+                -0.5 * z * z - log_std - LOG_SQRT_TAU
+            })
+            .sum()
+    }
+
+    fn collect(&mut self, stats: &mut Iteration) -> Batch {
+        let mut batch = Batch::default();
+        let mut completed = 0.0;
+        for _ in 0..self.config.steps {
+            let mut actions = Vec::with_capacity(self.config.envs);
+            for observation in &self.observations {
+                self.observation_stats.update(observation);
+                let mean = self.policy.forward(observation);
+                let action: Vec<f32> = mean
+                    .iter()
+                    .zip(&self.log_std)
+                    .map(|(mu, log_std)| mu + log_std.exp() * self.rng.normal())
+                    .collect();
+                batch.log_probs.push(self.log_prob(&mean, &action));
+                batch.values.push(self.value.forward(observation)[0]);
+                actions.push(action);
+            }
+            let step = self.envs.step(&actions);
+            for env in 0..self.config.envs {
+                let done = step.terminated[env] || step.truncated[env];
+                self.running_returns[env] += step.rewards[env];
+                if done {
+                    completed += self.running_returns[env];
+                    stats.episodes += 1;
+                    self.running_returns[env] = 0.0;
+                    if let Some(curriculum) = &mut self.curriculum
+                        && curriculum.record(step.success[env])
+                    {
+                        self.envs.configure(&curriculum.apply(&self.config.env));
+                    }
+                }
+                let reward = if self.config.normalize_rewards {
+                    let clip = self.config.reward_clip;
+                    self.return_normalizer
+                        .normalize(env, step.rewards[env], done, clip)
+                } else {
+                    step.rewards[env]
+                };
+                let bootstrap = match &step.final_observations[env] {
+                    Some(last) if step.truncated[env] && !step.terminated[env] => {
+                        self.value.forward(last)[0]
+                    }
+                    _ => 0.0,
+                };
+                batch.rewards.push(reward);
+                batch.terminated.push(step.terminated[env]);
+                batch.truncated.push(step.truncated[env]);
+                batch.bootstrap.push(bootstrap);
+            }
+            batch
+                .observations
+                .extend(std::mem::replace(&mut self.observations, step.observations));
+            batch.actions.extend(actions);
+        }
+        if stats.episodes > 0 {
+            stats.mean_return = completed / stats.episodes as f32;
+        }
+
+        // generalized advantage estimation, walking the rollout backwards
+        let envs = self.config.envs;
+        let mut next_values: Vec<f32> = self
+            .observations
+            .iter()
+            .map(|observation| self.value.forward(observation)[0])
+            .collect();
+        let mut next_advantages = vec![0.0; envs];
+        batch.advantages = vec![0.0; batch.rewards.len()];
+        for t in (0..self.config.steps).rev() {
+            for env in 0..envs {
+                let i = t * envs + env;
+                // the next stored value belongs to another episode once this one ended
+                let (next_value, live) = if batch.terminated[i] {
+                    (0.0, 0.0)
+                } else if batch.truncated[i] {
+                    (batch.bootstrap[i], 0.0)
+                } else {
+                    (next_values[env], 1.0)
+                };
+                let delta = batch.rewards[i] + self.config.gamma * next_value - batch.values[i];
+                next_advantages[env] =
+                    delta + self.config.gamma * self.config.lambda * live * next_advantages[env];
+                batch.advantages[i] = next_advantages[env];
+                next_values[env] = batch.values[i];
+            }
+        }
+        batch.returns = batch
+            .advantages
+            .iter()
+            .zip(&batch.values)
+            .map(|(a, v)| a + v)
+            .collect();
+        batch
+    }
+
+    /// Accumulates the loss gradients of sample `i`, weighted by `scale`
+    fn accumulate(
+        &self,
+        batch: &Batch,
+        i: usize,
+        advantage: f32,
+        scale: f32,
+        grads: &mut Gradients,
+        stats: &mut Iteration,
+    ) {
+        let config = &self.config;
+        let trace = self.policy.forward_trace(&batch.observations[i]);
+        let mean = trace.last().expect("trace ends with the output");
+        let action = &batch.actions[i];
+        let ratio = (self.log_prob(mean, action) - batch.log_probs[i]).exp();
+        let clipped = ratio.clamp(1.0 - config.clip, 1.0 + config.clip);
+        stats.policy_loss -= scale * (ratio * advantage).min(clipped * advantage);
+
+        // d(-surrogate)/d(log prob), zero where the clipped branch is the minimum
+        let unclipped = ratio * advantage <= clipped * advantage;
+        let grad_log_prob = if unclipped {
+            -scale * ratio * advantage
+        } else {
+            0.0
+        };
+        let network = grads.policy.len() - self.log_std.len();
+        let (network_grads, log_std_grads) = grads.policy.split_at_mut(network);
+        let mut grad_mean = Vec::with_capacity(mean.len());
+        for (j, (mu, a)) in mean.iter().zip(action).enumerate() {
+            let variance = (2.0 * self.log_std[j]).exp();
+            grad_mean.push(grad_log_prob * (a - mu) / variance);
+            log_std_grads[j] += grad_log_prob * ((a - mu) * (a - mu) / variance - 1.0)
+                - scale * config.entropy_coef;
+        }
+        self.policy.backward(&trace, &grad_mean, network_grads);
+
+        let trace = self.value.forward_trace(&batch.observations[i]);
+        let error = trace.last().expect("trace ends with the output")[0] - batch.returns[i];
+        stats.value_loss += scale * error * error;
+        self.value.backward(
+            &trace,
+            &[2.0 * config.value_coef * scale * error],
+            &mut grads.value,
+        );
+    }
+
+    /// Collects one rollout and runs the PPO epochs over it
+    pub fn step(&mut self) -> Iteration {
+        let mut stats = Iteration::default();
+        if self.config.normalize_observations {
+            let normalization = self
+                .observation_stats
+                .normalization(self.config.observation_clip);
+            self.policy.input = Some(normalization.clone());
+            self.value.input = Some(normalization);
+        }
+        let batch = self.collect(&mut stats);
+        let samples = batch.rewards.len();
+        self.total_steps += samples as u64;
+
+        let mut indices: Vec<usize> = (0..samples).collect();
+        let mut updates = 0;
+        for _ in 0..self.config.epochs {
+            // Fisher-Yates shuffle
+            for i in (1..indices.len()).rev() {
+                let j = (self.rng.next_u64() % (i as u64 + 1)) as usize;
+                indices.swap(i, j);
+            }
+            for minibatch in indices.chunks(self.config.minibatch.max(1)) {
+                let advantages: Vec<f32> = minibatch.iter().map(|&i| batch.advantages[i]).collect();
+                let mean = advantages.iter().sum::<f32>() / advantages.len() as f32;
+                let std = (advantages.iter().map(|a| (a - mean).powi(2)).sum::<f32>()
+                    / advantages.len() as f32)
+                    .sqrt();
+
+                let mut grads = Gradients {
+                    policy: vec![0.0; self.policy.parameters().len() + self.log_std.len()],
+                    value: vec![0.0; self.value.parameters().len()],
+                };
+                let scale = 1.0 / minibatch.len() as f32;
+                for (&i, advantage) in minibatch.iter().zip(advantages) {
+                    self.accumulate(
+                        &batch,
+                        i,
+                        (advantage - mean) / (std + 1e-8),
+                        scale,
+                        &mut grads,
+                        &mut stats,
+                    );
+                }
+                clip_norm(&mut grads.policy, self.config.max_grad_norm);
+                clip_norm(&mut grads.value, self.config.max_grad_norm);
+
+                let mut parameters = self.policy.parameters();
+                parameters.extend(&self.log_std);
+                self.policy_optimizer.step(&mut parameters, &grads.policy);
+                let network = parameters.len() - self.log_std.len();
+                self.policy.set_parameters(&parameters[..network]);
+                self.log_std.copy_from_slice(&parameters[network..]);
+
+                let mut parameters = self.value.parameters();
+                self.value_optimizer.step(&mut parameters, &grads.value);
+                self.value.set_parameters(&parameters);
+                updates += 1;
+            }
+        }
+
+        self.iteration += 1;
+        stats.index = self.iteration;
+        stats.total_steps = self.total_steps;
+        stats.policy_loss /= updates.max(1) as f32;
+        stats.value_loss /= updates.max(1) as f32;
+        stats.entropy = self.log_std.iter().map(|s| s + 0.5 + LOG_SQRT_TAU).sum();
+        stats.level = self.curriculum.as_ref().map(|curriculum| curriculum.level);
+        stats
+    }
+}
+
+fn clip_norm(grads: &mut [f32], max_norm: f32) {
+    let norm = grads.iter().map(|g| g * g).sum::<f32>().sqrt();
+    if norm > max_norm {
+        for g in grads.iter_mut() {
+            *g *= max_norm / norm;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loss whose gradients [`PpoTrainer::accumulate`] adds up for sample 0 of `batch`:
+    /// clipped surrogate, weighted value error and entropy bonus
+    fn loss(trainer: &PpoTrainer, batch: &Batch, advantage: f32) -> f32 {
+        let mut grads = gradients(trainer);
+        let mut stats = Iteration::default();
+        trainer.accumulate(batch, 0, advantage, 1.0, &mut grads, &mut stats);
+        stats.policy_loss + trainer.config.value_coef * stats.value_loss
+            - trainer.config.entropy_coef * trainer.log_std.iter().sum::<f32>()
+    }
+
+    fn gradients(trainer: &PpoTrainer) -> Gradients {
+        Gradients {
+            policy: vec![0.0; trainer.policy.parameters().len() + trainer.log_std.len()],
+            value: vec![0.0; trainer.value.parameters().len()],
+        }
+    }
+
+    /// Policy network parameters followed by `log_std`, as in [`Gradients::policy`]
+    fn policy_parameters(trainer: &PpoTrainer) -> Vec<f32> {
+        let mut parameters = trainer.policy.parameters();
+        parameters.extend(&trainer.log_std);
+        parameters
+    }
+
+    fn set_policy_parameters(trainer: &mut PpoTrainer, parameters: &[f32]) {
+        let network = parameters.len() - trainer.log_std.len();
+        trainer.policy.set_parameters(&parameters[..network]);
+        trainer.log_std.copy_from_slice(&parameters[network..]);
+    }
+
+    #[test]
+    fn resumes_from_a_checkpoint() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("rsrl-ppo-{}", std::process::id()));
+        let config = PpoConfig {
+            envs: 2,
+            steps: 16,
+            epochs: 1,
+            minibatch: 16,
+            hidden: 8,
+            ..PpoConfig::default()
+        };
+        let mut trainer = PpoTrainer::new(config.clone());
+        trainer.step();
+        trainer.save_checkpoint(&dir)?;
+
+        let mut resumed = PpoTrainer::resume(config.clone(), &dir)?;
+        assert_eq!(resumed.iteration, trainer.iteration);
+        assert_eq!(resumed.total_steps, trainer.total_steps);
+        assert_eq!(resumed.policy.parameters(), trainer.policy.parameters());
+        assert_eq!(resumed.value.parameters(), trainer.value.parameters());
+        assert_eq!(resumed.log_std, trainer.log_std);
+        // the resumed workers do not start over from the episodes of a fresh run
+        let fresh = PpoTrainer::new(config);
+        assert_ne!(resumed.observations, fresh.observations);
+        resumed.step();
+        assert_eq!(resumed.iteration, trainer.iteration + 1);
+        std::fs::remove_dir_all(dir)
+    }
+
+    #[test]
+    fn accumulate_matches_finite_differences() {
+        let mut trainer = PpoTrainer::new(PpoConfig {
+            envs: 1,
+            hidden: 8,
+            entropy_coef: 0.01,
+            normalize_observations: false,
+            ..PpoConfig::default()
+        });
+        let mut rng = Rng::new(1);
+        let observation: Vec<f32> = (0..trainer.config.env.observation_size())
+            .map(|_| rng.range(-1.0, 1.0))
+            .collect();
+        let mean = trainer.policy.forward(&observation);
+        let action: Vec<f32> = mean.iter().map(|mu| mu + rng.range(-0.5, 0.5)).collect();
+        // a ratio of about 1.05, inside the clip range
+        let batch = Batch {
+            log_probs: vec![trainer.log_prob(&mean, &action) - 0.05],
+            observations: vec![observation],
+            actions: vec![action],
+            returns: vec![1.0],
+            ..Batch::default()
+        };
+        let advantage = 0.7;
+        let mut grads = gradients(&trainer);
+        trainer.accumulate(
+            &batch,
+            0,
+            advantage,
+            1.0,
+            &mut grads,
+            &mut Iteration::default(),
+        );
+
+        let eps = 1e-2;
+        let check = |analytic: f32, up: f32, down: f32, name: &str| {
+            let numeric = (up - down) / (2.0 * eps);
+            assert!(
+                (analytic - numeric).abs() < 1e-3,
+                "{name}: {analytic} against {numeric}"
+            );
+        };
+        let parameters = policy_parameters(&trainer);
+        for (i, &analytic) in grads.policy.iter().enumerate() {
+            let mut shifted = parameters.clone();
+            shifted[i] += eps;
+            set_policy_parameters(&mut trainer, &shifted);
+            let up = loss(&trainer, &batch, advantage);
+            shifted[i] -= 2.0 * eps;
+            set_policy_parameters(&mut trainer, &shifted);
+            let down = loss(&trainer, &batch, advantage);
+            check(analytic, up, down, &format!("policy parameter {i}"));
+        }
+        set_policy_parameters(&mut trainer, &parameters);
+
+        let parameters = trainer.value.parameters();
+        for (i, &analytic) in grads.value.iter().enumerate() {
+            let mut shifted = parameters.clone();
+            shifted[i] += eps;
+            trainer.value.set_parameters(&shifted);
+            let up = loss(&trainer, &batch, advantage);
+            shifted[i] -= 2.0 * eps;
+            trainer.value.set_parameters(&shifted);
+            let down = loss(&trainer, &batch, advantage);
+            check(analytic, up, down, &format!("value parameter {i}"));
+        }
+    }
+}
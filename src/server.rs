@@ -0,0 +1,302 @@
+//! Line-delimited JSON protocol letting external trainers drive a golem environment.
+//!
+//! Each request is one JSON object on its own line, answered by exactly one JSON line:
+//!
+//! ```text
+//! > {"cmd": "spaces"}
+//! < {"observation_size": 15, "action_size": 2, "action_low": 0.0, "action_high": 1.0, "discrete": false}
+//! > {"cmd": "reset", "seed": 3}
+//! < {"observation": [...]}
+//! > {"cmd": "step", "action": [1.0, 0.0]}
+//! < {"observation": [...], "reward": 0.01, "terminated": false, "truncated": false}
+//! > {"cmd": "make", "env": "golem-climb-v0"}
+//! < {"observation_size": 30, "action_size": 2, "action_low": 0.0, "action_high": 1.0, "discrete": false}
+//! > {"cmd": "close"}
+//! < {"closed": true}
+//! ```
+//!
+//! Discrete action spaces take integer values from `action_low` to `action_high`. `make`
+//! swaps the environment for the [`Registry`] task of that name and answers with its spaces.
+//!
+//! Malformed requests are answered with `{"error": "..."}` and leave the connection open.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::env::{EnvConfig, GolemEnv};
+use crate::game::registry::Registry;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("server error: {0}")]
+    Remote(String),
+    #[error("connection closed")]
+    Closed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Reset {
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    Step {
+        action: Vec<f32>,
+    },
+    Spaces,
+    Make {
+        env: String,
+    },
+    Close,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Response {
+    Step {
+        observation: Vec<f32>,
+        reward: f32,
+        terminated: bool,
+        truncated: bool,
+    },
+    Reset {
+        observation: Vec<f32>,
+    },
+    Spaces {
+        observation_size: usize,
+        action_size: usize,
+        action_low: f32,
+        action_high: f32,
+        discrete: bool,
+    },
+    Closed {
+        closed: bool,
+    },
+    Error {
+        error: String,
+    },
+}
+
+fn spaces(env: &GolemEnv) -> Response {
+    Response::Spaces {
+        observation_size: env.observation_size(),
+        action_size: env.action_size(),
+        action_low: 0.0,
+        action_high: env.action_space().high(),
+        discrete: env.action_space().is_discrete(),
+    }
+}
+
+fn handle(env: &mut GolemEnv, request: Request) -> Response {
+    match request {
+        Request::Reset { seed } => {
+            if let Some(seed) = seed {
+                env.reseed(seed);
+            }
+            Response::Reset {
+                observation: env.reset(),
+            }
+        }
+        Request::Step { action } => {
+            if action.len() != env.action_size() {
+                return Response::Error {
+                    error: format!(
+                        "expected {} action values, got {}",
+                        env.action_size(),
+                        action.len()
+                    ),
+                };
+            }
+            let step = env.step(&action);
+            Response::Step {
+                observation: step.observation,
+                reward: step.reward,
+                terminated: step.terminated,
+                truncated: step.truncated,
+            }
+        }
+        Request::Spaces => spaces(env),
+        Request::Make { env: name } => match Registry::default().make(&name) {
+            Ok(config) => {
+                *env = GolemEnv::new(config);
+                spaces(env)
+            }
+            Err(e) => Response::Error {
+                error: e.to_string(),
+            },
+        },
+        Request::Close => Response::Closed { closed: true },
+    }
+}
+
+/// Answers requests read from `stream` until the client closes or disconnects
+pub fn serve_stream<S: Read + Write>(stream: S, config: EnvConfig) -> Result<(), ServerError> {
+    let mut env = GolemEnv::new(config);
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, close) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let close = matches!(request, Request::Close);
+                (handle(&mut env, request), close)
+            }
+            Err(e) => (
+                Response::Error {
+                    error: e.to_string(),
+                },
+                false,
+            ),
+        };
+        let writer = stream.get_mut();
+        serde_json::to_writer(&mut *writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Serves clients one after another, each on a fresh environment
+///
+/// `addr` is a TCP address such as `127.0.0.1:5555`, or `unix:<path>` for a Unix socket.
+pub fn serve(addr: &str, config: EnvConfig) -> Result<(), ServerError> {
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        println!("serving golem env on {addr}");
+        for stream in listener.incoming() {
+            if let Err(e) = serve_stream(stream?, config.clone()) {
+                eprintln!("client disconnected: {e}");
+            }
+        }
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(addr)?;
+    println!("serving golem env on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        if let Err(e) = serve_stream(stream?, config.clone()) {
+            eprintln!("client disconnected: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Minimal client of the protocol, the reference for trainers in other languages
+pub struct Client<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl Client<TcpStream> {
+    pub fn connect(addr: &str) -> Result<Self, ServerError> {
+        Ok(Self::new(TcpStream::connect(addr)?))
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    pub fn request(&mut self, request: &Request) -> Result<Response, ServerError> {
+        let writer = self.stream.get_mut();
+        serde_json::to_writer(&mut *writer, request)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(ServerError::Closed);
+        }
+        match serde_json::from_str(&line)? {
+            Response::Error { error } => Err(ServerError::Remote(error)),
+            response => Ok(response),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    #[test]
+    fn client_drives_served_env() {
+        let (server, client) = UnixStream::pair().expect("socket pair");
+        let config = EnvConfig::default();
+        let (observation_size, action_size) = (config.observation_size(), config.action_size());
+        let handle = std::thread::spawn(move || serve_stream(server, config));
+        let mut client = Client::new(client);
+
+        let Ok(Response::Spaces {
+            observation_size: observed,
+            action_size: actions,
+            discrete: false,
+            ..
+        }) = client.request(&Request::Spaces)
+        else {
+            panic!("expected the spaces");
+        };
+        assert_eq!((observed, actions), (observation_size, action_size));
+
+        let Ok(Response::Reset { observation }) = client.request(&Request::Reset { seed: Some(3) })
+        else {
+            panic!("expected the first observation");
+        };
+        assert_eq!(observation.len(), observation_size);
+
+        let action = vec![0.5; action_size];
+        let Ok(Response::Step { observation, .. }) = client.request(&Request::Step { action })
+        else {
+            panic!("expected a step");
+        };
+        assert_eq!(observation.len(), observation_size);
+
+        let action = vec![0.5; action_size + 1];
+        assert!(matches!(
+            client.request(&Request::Step { action }),
+            Err(ServerError::Remote(_))
+        ));
+
+        // a malformed line is answered with an error and leaves the connection open
+        let writer = client.stream.get_mut();
+        writer
+            .write_all(b"{\"cmd\": \"step\", \"action\": [0.5,\n")
+            .expect("write");
+        let mut line = String::new();
+        client.stream.read_line(&mut line).expect("read");
+        assert!(matches!(
+            serde_json::from_str(&line),
+            Ok(Response::Error { .. })
+        ));
+        assert!(matches!(
+            client.request(&Request::Spaces),
+            Ok(Response::Spaces { .. })
+        ));
+
+        assert!(matches!(
+            client.request(&Request::Close),
+            Ok(Response::Closed { closed: true })
+        ));
+        assert!(handle.join().expect("server thread").is_ok());
+    }
+}